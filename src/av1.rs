@@ -1,20 +1,60 @@
 use std::fmt;
 
-use crate::common::ProfileConstraint;
+use crate::common::{
+    self, Bitrate, Check, Explanation, Framerate, Headroom, Limit, ProfileConstraint,
+    SelectionError, TileError, TileLayout,
+};
+use crate::hrd::{self, BitWriter, HrdError, HrdReport};
 use yuv::color::ChromaSampling;
 use yuv::color::Depth;
 
+/// NUM_REF_FRAMES, AV1 decoders always keep 8 reference frame slots
+pub const NUM_REF_FRAMES: u32 = 8;
+/// Widest tile in luma samples
+pub const MAX_TILE_WIDTH: u32 = 4096;
+/// Largest tile area in luma samples
+pub const MAX_TILE_AREA: u32 = 4096 * 2304;
+pub const MAX_TILE_COLS: u32 = 64;
+pub const MAX_TILE_ROWS: u32 = 64;
+/// Superblock sizes in luma samples
+pub const SUPERBLOCK_SIZES: [u32; 2] = [64, 128];
+/// libaom's default decoder_buffer_delay in 1/90000 s
+pub const DECODER_BUFFER_DELAY: u32 = 70_000;
+/// libaom's default encoder_buffer_delay in 1/90000 s, with the decoder delay it adds up
+/// to the one second BufferSize lasts at the level's BitRate
+pub const ENCODER_BUFFER_DELAY: u32 = 20_000;
+
 #[derive(Debug)]
 pub struct LevelSelector {
     // Constraints
     width: u32,
     height: u32,
-    framerate: f32,
+    framerate: Framerate,
+    /// Decoded frames per second, shown and hidden, defaults to the framerate
+    decode_framerate: Option<Framerate>,
+    /// Frame headers per second, defaults to the larger of the two frame rates
+    header_rate: Option<Framerate>,
     tier: Tier,
+    /// Set by `auto_tier`, lets selection fall back to High tier per level
+    auto_tier: bool,
     profile: Profile,
+    /// Set by `auto_profile`, holds whether the stream is intra only
+    auto_profile: Option<bool>,
+    chroma: ChromaSampling,
+    bit_depth: Depth,
     min_level: Option<Level>,
     max_level: Option<Level>,
-    max_bitrate: Option<u32>,
+    max_bitrate: Option<Bitrate>,
+    dpb_frames: Option<u32>,
+    /// Largest coded picture in bytes
+    max_picture_bytes: Option<u64>,
+    headroom: Headroom,
+}
+
+impl Default for LevelSelector {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LevelSelector {
@@ -23,13 +63,22 @@ impl LevelSelector {
             // Define default behaviour if no constraints are set
             width: 1920,
             height: 1080,
-            framerate: 30.0,
+            framerate: Framerate::integer(30),
+            decode_framerate: None,
+            header_rate: None,
             tier: Tier::Main,
+            auto_tier: false,
             profile: Profile::Main,
+            auto_profile: None,
+            chroma: ChromaSampling::Cs420,
+            bit_depth: Depth::Depth8,
             // Ignore if not set
             min_level: None,
             max_level: None,
             max_bitrate: None,
+            dpb_frames: None,
+            max_picture_bytes: None,
+            headroom: Headroom::Minimum,
         }
     }
     pub fn width(mut self, width: u32) -> Self {
@@ -40,8 +89,20 @@ impl LevelSelector {
         self.height = height;
         self
     }
-    pub fn framerate(mut self, framerate: f32) -> Self {
-        self.framerate = framerate;
+    pub fn framerate(mut self, framerate: impl Into<Framerate>) -> Self {
+        self.framerate = framerate.into();
+        self
+    }
+    /// Frames decoded per second including hidden frames such as alt-refs, checked
+    /// against MaxDecodeRate. Defaults to the displayed framerate.
+    pub fn decode_framerate(mut self, framerate: impl Into<Framerate>) -> Self {
+        self.decode_framerate = Some(framerate.into());
+        self
+    }
+    /// Frame headers per second including `show_existing_frame` headers, checked against
+    /// MaxHeaderRate. Defaults to the larger of the displayed and decoded framerates.
+    pub fn header_rate(mut self, rate: impl Into<Framerate>) -> Self {
+        self.header_rate = Some(rate.into());
         self
     }
     pub fn clamp(mut self, min: Level, max: Level) -> Self {
@@ -49,59 +110,276 @@ impl LevelSelector {
         self.max_level = Some(max);
         self
     }
-    pub fn max_bitrate(mut self, max_bitrate: u32) -> Self {
+    pub fn max_bitrate(mut self, max_bitrate: Bitrate) -> Self {
         self.max_bitrate = Some(max_bitrate);
         self
     }
     pub fn tier(mut self, tier: Tier) -> Self {
         self.tier = tier;
+        self.auto_tier = false;
+        self
+    }
+    /// Lets selection pick the tier, each level is tried at Main tier and then at High
+    /// tier before moving up, so L4.1 High is preferred over L5 Main
+    pub fn auto_tier(mut self) -> Self {
+        self.auto_tier = true;
         self
     }
     pub fn profile(mut self, profile: Profile) -> Self {
         self.profile = profile;
+        self.auto_profile = None;
+        self
+    }
+    /// Picks the least demanding profile that carries the chroma format and bit depth,
+    /// see `Profile::for_format`
+    pub fn auto_profile(mut self, intra_only: bool) -> Self {
+        self.auto_profile = Some(intra_only);
+        self
+    }
+    /// Chroma subsampling of the content, defaults to 4:2:0
+    pub fn chroma(mut self, chroma: ChromaSampling) -> Self {
+        self.chroma = chroma;
+        self
+    }
+    /// Bit depth of the content, defaults to 8 bit
+    pub fn bit_depth(mut self, bit_depth: Depth) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+    /// Number of frames the decoded picture buffer has to hold
+    pub fn dpb_frames(mut self, frames: u32) -> Self {
+        self.dpb_frames = Some(frames);
+        self
+    }
+    /// Number of reference frames the encoder keeps, every level has the same
+    /// `NUM_REF_FRAMES` slots so this only fails above 8
+    pub fn reference_frames(self, frames: u32) -> Self {
+        self.dpb_frames(frames)
+    }
+    /// Largest coded picture in bytes, usually a keyframe, checked against the level's
+    /// minimum compression ratio
+    pub fn max_picture_bytes(mut self, bytes: u64) -> Self {
+        self.max_picture_bytes = Some(bytes);
+        self
+    }
+    /// How much room to leave above the configuration, defaults to `Headroom::Minimum`
+    pub fn headroom(mut self, headroom: Headroom) -> Self {
+        self.headroom = headroom;
         self
     }
 
-    pub fn select(self) -> Option<LevelSpecification> {
-        let samples = (self.width as u64) * (self.height as u64);
-        let display_rate = (samples as f64 * self.framerate as f64) as u64;
-
-        for level in LEVEL_DETAILS.iter() {
-            if samples <= level.max_picture_size()
-                && display_rate <= level.max_display_rate()
-                && self.width <= level.max_width()
-                && self.height <= level.max_height()
-                && self.framerate.ceil() as u32 <= level.max_header_rate()
-            {
-                let selected = match (self.max_bitrate, level.max_bit_rate(self.tier)) {
-                    (Some(bitrate_constraint), Some(level_max_bitrate))
-                        if level_max_bitrate >= bitrate_constraint.into() =>
-                    {
-                        *level
-                    }
-                    (None, Some(_)) => *level,
-                    _ => continue,
-                };
+    /// The profile selection runs with, either the one set with `profile` or the pick
+    /// of `auto_profile`
+    pub fn resolve_profile(&self) -> Result<Profile, SelectionError> {
+        let profile = match self.auto_profile {
+            Some(intra_only) => Profile::for_format(self.chroma, self.bit_depth, intra_only),
+            None => Some(self.profile),
+        };
+        profile
+            .filter(|p| ProfileConstraint::from(p).supports(self.chroma, self.bit_depth))
+            .ok_or(SelectionError::UnsupportedFormat)
+    }
 
-                // Clamp to min level
-                match self.min_level {
-                    Some(min) if selected.id() < min => {
-                        continue;
-                    }
-                    _ => {}
-                }
+    pub fn select(self) -> Result<LevelSpecification, SelectionError> {
+        common::select_level(
+            &LEVEL_DETAILS,
+            self.min_level,
+            self.max_level,
+            self.headroom,
+            |level| self.checks(level),
+        )
+    }
 
-                // Check if exceeds max level
-                match self.max_level {
-                    Some(max) if selected.id() > max => return None,
-                    _ => {}
-                }
+    /// Like `select` but also reports every level that was skipped and the limits it failed
+    pub fn select_explained(self) -> Explanation<LevelSpecification> {
+        common::explain_level(
+            &LEVEL_DETAILS,
+            self.min_level,
+            self.max_level,
+            self.headroom,
+            |level| self.checks(level),
+        )
+    }
 
-                return Some(selected);
-            }
+    /// Reports how much of each limit of `level` the configuration uses
+    pub fn utilization(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        self.checks(level)
+    }
+
+    /// Like `select` but also returns the tier the level was selected at, which is only
+    /// different from the configured tier with `auto_tier`
+    pub fn select_with_tier(self) -> Result<(LevelSpecification, Tier), SelectionError> {
+        let level = common::select_level(
+            &LEVEL_DETAILS,
+            self.min_level,
+            self.max_level,
+            self.headroom,
+            |level| self.checks(level),
+        )?;
+        Ok((level, self.tier_for(&level)?))
+    }
+
+    /// The tier `level` is checked at, with `auto_tier` that's Main unless only High tier
+    /// fits the configuration with the requested headroom
+    fn tier_for(&self, level: &LevelSpecification) -> Result<Tier, SelectionError> {
+        if !self.auto_tier {
+            return Ok(self.tier);
+        }
+        let fraction = self.headroom.fraction()?;
+        if self
+            .tier_checks(level, Tier::Main)?
+            .iter()
+            .all(|c| c.within(fraction))
+        {
+            return Ok(Tier::Main);
+        }
+        if level.max_bit_rate_high.is_some() {
+            Ok(Tier::High)
+        } else {
+            Ok(Tier::Main)
         }
+    }
+
+    fn checks(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        self.tier_checks(level, self.tier_for(level)?)
+    }
+
+    fn tier_checks(
+        &self,
+        level: &LevelSpecification,
+        tier: Tier,
+    ) -> Result<Vec<Check>, SelectionError> {
+        common::validate(self.width, self.height, self.framerate)?;
+        let profile = self.resolve_profile()?;
+        let samples = (self.width as u64) * (self.height as u64);
+        let display_rate = self
+            .framerate
+            .per_second(samples)
+            .ok_or(SelectionError::Overflow)?;
+        let decode_framerate = self.decode_framerate.unwrap_or(self.framerate);
+        if !decode_framerate.is_valid() || !self.header_rate.is_none_or(|r| r.is_valid()) {
+            return Err(SelectionError::InvalidFramerate);
+        }
+        let decode_rate = decode_framerate
+            .per_second(samples)
+            .ok_or(SelectionError::Overflow)?;
+        let header_rate = match self.header_rate {
+            Some(rate) => rate.ceil(),
+            None => self.framerate.ceil().max(decode_framerate.ceil()),
+        }
+        .ok_or(SelectionError::Overflow)?;
 
-        Some(LEVEL_DETAILS[LEVEL_DETAILS.len() - 1])
+        let mut checks = vec![
+            Check::new(Limit::PictureSize, samples, Some(level.max_picture_size())),
+            Check::new(
+                Limit::DisplayRate,
+                display_rate,
+                Some(level.max_display_rate()),
+            ),
+            Check::new(
+                Limit::DecodeRate,
+                decode_rate,
+                Some(level.max_decode_rate()),
+            ),
+            Check::new(
+                Limit::Width,
+                self.width.into(),
+                Some(level.max_width().into()),
+            ),
+            Check::new(
+                Limit::Height,
+                self.height.into(),
+                Some(level.max_height().into()),
+            ),
+            Check::new(
+                Limit::HeaderRate,
+                header_rate,
+                Some(level.max_header_rate().into()),
+            ),
+            Check::new(
+                Limit::Bitrate,
+                self.max_bitrate.unwrap_or_default().bps(),
+                level.max_bit_rate(profile, tier).map(|b| b.bps()),
+            ),
+        ];
+        if let Some(bytes) = self.max_picture_bytes {
+            checks.push(Check::new(
+                Limit::CodedPictureBytes,
+                bytes,
+                Some(level.max_coded_picture_bytes(
+                    self.width,
+                    self.height,
+                    profile,
+                    decode_framerate,
+                )?),
+            ));
+        }
+        if let Some(frames) = self.dpb_frames {
+            checks.push(Check::new(
+                Limit::DpbFrames,
+                frames.into(),
+                Some(NUM_REF_FRAMES.into()),
+            ));
+        }
+        Ok(checks)
+    }
+}
+
+impl common::LevelSelector for LevelSelector {
+    type Level = Level;
+    type Profile = Profile;
+    type Specification = LevelSpecification;
+
+    fn width(self, width: u32) -> Self {
+        LevelSelector::width(self, width)
+    }
+    fn height(self, height: u32) -> Self {
+        LevelSelector::height(self, height)
+    }
+    fn framerate(self, framerate: impl Into<Framerate>) -> Self {
+        LevelSelector::framerate(self, framerate)
+    }
+    fn clamp(self, min: Level, max: Level) -> Self {
+        LevelSelector::clamp(self, min, max)
+    }
+    fn max_bitrate(self, max_bitrate: Bitrate) -> Self {
+        LevelSelector::max_bitrate(self, max_bitrate)
+    }
+    fn profile(self, profile: Profile) -> Self {
+        LevelSelector::profile(self, profile)
+    }
+    fn auto_profile(self, intra_only: bool) -> Self {
+        LevelSelector::auto_profile(self, intra_only)
+    }
+    fn chroma(self, chroma: ChromaSampling) -> Self {
+        LevelSelector::chroma(self, chroma)
+    }
+    fn bit_depth(self, bit_depth: Depth) -> Self {
+        LevelSelector::bit_depth(self, bit_depth)
+    }
+    fn dpb_frames(self, frames: u32) -> Self {
+        LevelSelector::dpb_frames(self, frames)
+    }
+    fn reference_frames(self, frames: u32) -> Self {
+        LevelSelector::reference_frames(self, frames)
+    }
+    fn max_picture_bytes(self, bytes: u64) -> Self {
+        LevelSelector::max_picture_bytes(self, bytes)
+    }
+    fn headroom(self, headroom: Headroom) -> Self {
+        LevelSelector::headroom(self, headroom)
+    }
+    fn resolve_profile(&self) -> Result<Profile, SelectionError> {
+        LevelSelector::resolve_profile(self)
+    }
+    fn select(self) -> Result<LevelSpecification, SelectionError> {
+        LevelSelector::select(self)
+    }
+    fn select_explained(self) -> Explanation<LevelSpecification> {
+        LevelSelector::select_explained(self)
+    }
+    fn utilization(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        LevelSelector::utilization(self, level)
     }
 }
 
@@ -112,6 +390,34 @@ pub enum Profile {
     Professional,
 }
 
+impl Profile {
+    /// The least demanding profile that can carry the format, AV1 has no intra only
+    /// profiles so `intra_only` makes no difference
+    pub fn for_format(chroma: ChromaSampling, bit_depth: Depth, _intra_only: bool) -> Option<Self> {
+        [Profile::Main, Profile::High, Profile::Professional]
+            .into_iter()
+            .find(|p| ProfileConstraint::from(p).supports(chroma, bit_depth))
+    }
+
+    /// PicSizeProfileFactor, bits per luma sample of an uncompressed picture
+    pub fn pic_size_profile_factor(&self) -> u64 {
+        match self {
+            Profile::Main => 15,
+            Profile::High => 30,
+            Profile::Professional => 36,
+        }
+    }
+
+    /// BitrateProfileFactor from Annex A
+    pub fn bitrate_multiplier(&self) -> f64 {
+        match self {
+            Profile::Main => 1.0,
+            Profile::High => 2.0,
+            Profile::Professional => 3.0,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Tier {
     Main,
@@ -256,7 +562,9 @@ pub struct LevelSpecification {
     max_display_rate: u64,
     max_decode_rate: u64,
     max_header_rate: u32,
+    /// MaxBitrate in bit/s for the Main tier
     max_bit_rate_main: u64,
+    /// MaxBitrate in bit/s for the High tier
     max_bit_rate_high: Option<u32>,
     min_comp_basis: u32,
     max_tiles: u32,
@@ -291,17 +599,20 @@ impl LevelSpecification {
     pub fn max_header_rate(&self) -> u32 {
         self.max_header_rate
     }
-    pub fn max_bit_rate(&self, tier: Tier) -> Option<u64> {
-        match tier {
-            Tier::Main => Some(self.max_bit_rate_main),
-            Tier::High => self.max_bit_rate_high.map(|v| v as u64),
-        }
+    /// MaxBitrate of the tier scaled by the profile's BitrateProfileFactor
+    pub fn max_bit_rate(&self, profile: Profile, tier: Tier) -> Option<Bitrate> {
+        let base = match tier {
+            Tier::Main => Some(self.max_bit_rate_main()),
+            Tier::High => self.max_bit_rate_high(),
+        };
+        base.map(|b| b.scale(profile.bitrate_multiplier()))
     }
-    pub fn max_bit_rate_main(&self) -> u64 {
-        self.max_bit_rate_main
+    pub fn max_bit_rate_main(&self) -> Bitrate {
+        Bitrate::from_bps(self.max_bit_rate_main)
     }
-    pub fn max_bit_rate_high(&self) -> Option<u32> {
+    pub fn max_bit_rate_high(&self) -> Option<Bitrate> {
         self.max_bit_rate_high
+            .map(|bps| Bitrate::from_bps(bps.into()))
     }
     pub fn min_comp_basis(&self) -> u32 {
         self.min_comp_basis
@@ -312,6 +623,271 @@ impl LevelSpecification {
     pub fn max_tile_cols(&self) -> u32 {
         self.max_tile_cols
     }
+
+    /// Checks a tile grid against the level's tile limits and the MAX_TILE_WIDTH,
+    /// MAX_TILE_AREA, MAX_TILE_COLS and MAX_TILE_ROWS of the spec
+    pub fn validate_tiles(
+        &self,
+        width: u32,
+        height: u32,
+        layout: &TileLayout,
+    ) -> Result<(), TileError> {
+        if width == 0 || height == 0 {
+            return Err(TileError::InvalidDimensions);
+        }
+        if !SUPERBLOCK_SIZES.contains(&layout.block_size) {
+            return Err(TileError::InvalidBlockSize);
+        }
+        if !layout.covers(width, height) {
+            return Err(TileError::Mismatch);
+        }
+        if layout.columns() > MAX_TILE_COLS.min(self.max_tile_cols) {
+            return Err(TileError::TooManyColumns);
+        }
+        if layout.rows() > MAX_TILE_ROWS {
+            return Err(TileError::TooManyRows);
+        }
+        if layout.tiles() > self.max_tiles {
+            return Err(TileError::TooManyTiles);
+        }
+        let sb_size = layout.block_size;
+        if let Some(i) = layout
+            .column_widths
+            .iter()
+            .position(|&w| w * sb_size > MAX_TILE_WIDTH)
+        {
+            return Err(TileError::TooWide(i));
+        }
+        let widest = layout.column_widths.iter().max().copied().unwrap_or(0) as u64;
+        let area = |h: u32| widest * h as u64 * (sb_size as u64 * sb_size as u64);
+        if let Some(i) = layout
+            .row_heights
+            .iter()
+            .position(|&h| area(h) > MAX_TILE_AREA as u64)
+        {
+            return Err(TileError::TooLarge(i));
+        }
+        Ok(())
+    }
+
+    /// Uniformly spaced tiles with the most tiles the level allows, for as many encoder
+    /// threads as possible
+    pub fn plan_tiles(&self, width: u32, height: u32, sb_size: u32) -> Result<TileInfo, TileError> {
+        if width == 0 || height == 0 {
+            return Err(TileError::InvalidDimensions);
+        }
+        if !SUPERBLOCK_SIZES.contains(&sb_size) {
+            return Err(TileError::InvalidBlockSize);
+        }
+        // tile_info() with uniform_tile_spacing_flag from 5.9.15
+        let sb_cols = width.div_ceil(sb_size);
+        let sb_rows = height.div_ceil(sb_size);
+        let max_tile_width_sb = MAX_TILE_WIDTH / sb_size;
+        let max_tile_area_sb = MAX_TILE_AREA / (sb_size * sb_size);
+        let min_log2_tile_cols = tile_log2(max_tile_width_sb, sb_cols);
+        let max_log2_tile_cols = tile_log2(1, sb_cols.min(MAX_TILE_COLS));
+        let max_log2_tile_rows = tile_log2(1, sb_rows.min(MAX_TILE_ROWS));
+        let min_log2_tiles = min_log2_tile_cols.max(tile_log2(max_tile_area_sb, sb_cols * sb_rows));
+
+        let mut best: Option<TileInfo> = None;
+        for tile_cols_log2 in min_log2_tile_cols..=max_log2_tile_cols {
+            let min_log2_tile_rows = min_log2_tiles.saturating_sub(tile_cols_log2);
+            for tile_rows_log2 in min_log2_tile_rows..=max_log2_tile_rows {
+                let info = TileInfo {
+                    tile_cols_log2,
+                    tile_rows_log2,
+                    layout: TileLayout {
+                        block_size: sb_size,
+                        column_widths: uniform_tiles(sb_cols, tile_cols_log2),
+                        row_heights: uniform_tiles(sb_rows, tile_rows_log2),
+                    },
+                };
+                if self.validate_tiles(width, height, &info.layout).is_err() {
+                    continue;
+                }
+                let key = |i: &TileInfo| (i.layout.tiles(), i.layout.columns());
+                if best.as_ref().is_none_or(|b| key(&info) > key(b)) {
+                    best = Some(info);
+                }
+            }
+        }
+        best.ok_or(TileError::TooManyTiles)
+    }
+
+    /// Largest shown frame in bytes when `framerate` frames are decoded per second,
+    /// hidden frames included. That's UncompressedSize divided by MinPicCompressRatio =
+    /// Max(0.8, MinCompBasis * SpeedAdj), where SpeedAdj is the decoded sample rate over
+    /// MaxDisplayRate.
+    pub fn max_coded_picture_bytes(
+        &self,
+        width: u32,
+        height: u32,
+        profile: Profile,
+        framerate: impl Into<Framerate>,
+    ) -> Result<u64, SelectionError> {
+        let framerate = framerate.into();
+        common::validate(width, height, framerate)?;
+        let samples = width as u64 * height as u64;
+        let uncompressed = samples
+            .checked_mul(profile.pic_size_profile_factor())
+            .ok_or(SelectionError::Overflow)?
+            >> 3;
+        let speed_adj = samples as f64 * framerate.as_f64() / self.max_display_rate as f64;
+        let min_ratio = (self.min_comp_basis as f64 * speed_adj).max(0.8);
+        Ok((uncompressed as f64 / min_ratio) as u64)
+    }
+
+    /// Operating parameters of the Annex E decoder model for a stream at `bitrate`.
+    ///
+    /// BufferSize is one second of the level's BitRate, the max bitrate for the profile
+    /// and tier. The delays are libaom's defaults which together fill that second, use
+    /// `OperatingParameters::with_delays` for others.
+    pub fn operating_parameters(
+        &self,
+        profile: Profile,
+        tier: Tier,
+        bitrate: Bitrate,
+        low_delay: bool,
+    ) -> Result<OperatingParameters, SelectionError> {
+        let max_bitrate = self
+            .max_bit_rate(profile, tier)
+            .ok_or(SelectionError::TierUnavailable)?;
+        if bitrate.bps() == 0 || bitrate > max_bitrate {
+            return Err(SelectionError::Bitrate);
+        }
+        Ok(OperatingParameters {
+            bitrate,
+            buffer_size: max_bitrate.bps(),
+            decoder_buffer_delay: DECODER_BUFFER_DELAY,
+            encoder_buffer_delay: ENCODER_BUFFER_DELAY,
+            low_delay_mode_flag: low_delay,
+        })
+    }
+}
+
+/// A uniformly spaced tile grid and the tile_info() values that signal it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileInfo {
+    pub tile_cols_log2: u32,
+    pub tile_rows_log2: u32,
+    /// Column widths and row heights in superblocks
+    pub layout: TileLayout,
+}
+
+/// Smallest k such that `block_size << k` is at least `target`
+fn tile_log2(block_size: u32, target: u32) -> u32 {
+    let mut k = 0;
+    while (block_size << k) < target {
+        k += 1;
+    }
+    k
+}
+
+/// Tile sizes of uniform spacing, every tile but the last is the same size
+fn uniform_tiles(sbs: u32, log2: u32) -> Vec<u32> {
+    let size = (sbs + (1 << log2) - 1) >> log2;
+    let count = sbs.div_ceil(size);
+    (0..count)
+        .map(|i| if i + 1 < count { size } else { sbs - i * size })
+        .collect()
+}
+
+/// decoder_model_info() of the sequence header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderModelInfo {
+    pub buffer_delay_length_minus_1: u8,
+    pub num_units_in_decoding_tick: u32,
+    pub buffer_removal_time_length_minus_1: u8,
+    pub frame_presentation_time_length_minus_1: u8,
+}
+
+impl DecoderModelInfo {
+    pub fn write(&self, w: &mut BitWriter) {
+        w.write_bits(self.buffer_delay_length_minus_1.into(), 5);
+        w.write_bits(self.num_units_in_decoding_tick.into(), 32);
+        w.write_bits(self.buffer_removal_time_length_minus_1.into(), 5);
+        w.write_bits(self.frame_presentation_time_length_minus_1.into(), 5);
+    }
+}
+
+/// Smoothing buffer of one operating point, delays are in 1/90000 s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatingParameters {
+    pub bitrate: Bitrate,
+    /// BufferSize in bits
+    pub buffer_size: u64,
+    pub decoder_buffer_delay: u32,
+    pub encoder_buffer_delay: u32,
+    pub low_delay_mode_flag: bool,
+}
+
+impl OperatingParameters {
+    /// Replaces the delays, which together can't last longer than BufferSize at the
+    /// level's BitRate, one second. Fails with `SelectionError::CpbSize` otherwise.
+    pub fn with_delays(
+        mut self,
+        decoder_buffer_delay: u32,
+        encoder_buffer_delay: u32,
+    ) -> Result<Self, SelectionError> {
+        let total_delay = decoder_buffer_delay as u64 + encoder_buffer_delay as u64;
+        if decoder_buffer_delay == 0 || total_delay > hrd::CLOCK_RATE {
+            return Err(SelectionError::CpbSize);
+        }
+        self.decoder_buffer_delay = decoder_buffer_delay;
+        self.encoder_buffer_delay = encoder_buffer_delay;
+        Ok(self)
+    }
+
+    /// decoder_model_info() to go with these parameters, with one decoding tick per frame
+    /// of a timing_info() whose time_scale and num_units_in_display_tick are the
+    /// framerate's numerator and denominator
+    pub fn decoder_model_info(&self, framerate: Framerate) -> DecoderModelInfo {
+        let longest = self
+            .decoder_buffer_delay
+            .max(self.encoder_buffer_delay)
+            .max(1);
+        let bits = u32::BITS - longest.leading_zeros();
+        DecoderModelInfo {
+            buffer_delay_length_minus_1: (bits - 1) as u8,
+            num_units_in_decoding_tick: framerate.den(),
+            buffer_removal_time_length_minus_1: 9,
+            frame_presentation_time_length_minus_1: 9,
+        }
+    }
+
+    /// operating_parameters_info(), `info` gives the delay field length
+    pub fn write(&self, info: &DecoderModelInfo, w: &mut BitWriter) {
+        let bits = info.buffer_delay_length_minus_1 as u32 + 1;
+        w.write_bits(self.decoder_buffer_delay.into(), bits);
+        w.write_bits(self.encoder_buffer_delay.into(), bits);
+        w.write_flag(self.low_delay_mode_flag);
+    }
+
+    /// Runs a trace through the smoothing buffer, in low delay mode frames may be
+    /// removed late so only overflows count
+    pub fn check(&self, frames: &[hrd::Frame]) -> Result<HrdReport, HrdError> {
+        let mut report = hrd::Hrd::new(self.bitrate, self.buffer_size)
+            .initial_delay(self.decoder_buffer_delay.into())
+            .simulate(frames)?;
+        if self.low_delay_mode_flag {
+            report.underflows.clear();
+        }
+        Ok(report)
+    }
+}
+
+impl common::LevelSpec for LevelSpecification {
+    type Level = Level;
+
+    fn id(&self) -> Level {
+        self.id
+    }
+    fn max_luma_picture_size(&self) -> u64 {
+        self.max_picture_size
+    }
+    fn max_luma_sample_rate(&self) -> u64 {
+        self.max_display_rate
+    }
 }
 
 pub const LEVEL_DETAILS: [LevelSpecification; 14] = [
@@ -522,15 +1098,36 @@ mod tests {
 
     #[test]
     fn max_bitrate() {
-        use crate::av1::{self, Level, Tier};
+        use crate::av1::{self, Level, Profile, Tier};
+        use crate::common::Bitrate;
 
         let l = av1::get(Level::L3);
-        assert_eq!(l.max_bit_rate(Tier::Main), Some(6_000_000));
-        assert_eq!(l.max_bit_rate(Tier::High), None);
+        assert_eq!(
+            l.max_bit_rate(Profile::Main, Tier::Main),
+            Some(Bitrate::from_bps(6_000_000))
+        );
+        assert_eq!(l.max_bit_rate(Profile::Main, Tier::High), None);
 
         let l = av1::get(Level::L5_2);
-        assert_eq!(l.max_bit_rate(Tier::Main), Some(60_000_000));
-        assert_eq!(l.max_bit_rate(Tier::High), Some(240_000_000));
+        assert_eq!(
+            l.max_bit_rate(Profile::Main, Tier::Main),
+            Some(Bitrate::from_bps(60_000_000))
+        );
+        assert_eq!(
+            l.max_bit_rate(Profile::Main, Tier::High),
+            Some(Bitrate::from_bps(240_000_000))
+        );
+
+        // BitrateProfileFactor is 2 for High and 3 for Professional
+        let l = av1::get(Level::L5_1);
+        assert_eq!(
+            l.max_bit_rate(Profile::High, Tier::Main),
+            Some(Bitrate::from_bps(80_000_000))
+        );
+        assert_eq!(
+            l.max_bit_rate(Profile::Professional, Tier::Main),
+            Some(Bitrate::from_bps(120_000_000))
+        );
     }
 
     #[test]
@@ -575,4 +1172,350 @@ mod tests {
             Level::L6_1
         );
     }
+
+    #[test]
+    fn auto_profile_cases() {
+        use crate::av1::{LevelSelector, Profile};
+        use crate::common::SelectionError;
+        use yuv::color::{ChromaSampling, Depth};
+
+        let profile = |chroma, bit_depth| {
+            LevelSelector::new()
+                .chroma(chroma)
+                .bit_depth(bit_depth)
+                .auto_profile(false)
+                .resolve_profile()
+        };
+
+        assert_eq!(
+            profile(ChromaSampling::Cs420, Depth::Depth10),
+            Ok(Profile::Main)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs444, Depth::Depth8),
+            Ok(Profile::High)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs420, Depth::Depth12),
+            Ok(Profile::Professional)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs422, Depth::Depth10),
+            Ok(Profile::Professional)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs444, Depth::Depth16),
+            Err(SelectionError::UnsupportedFormat)
+        );
+    }
+
+    #[test]
+    fn select_auto_tier_cases() {
+        use crate::av1::{Level, LevelSelector, Tier};
+        use crate::common::Bitrate;
+
+        let contribution = || {
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(60)
+                .max_bitrate(Bitrate::from_mbps(40))
+        };
+
+        assert_eq!(contribution().select().unwrap().id(), Level::L5_1);
+        let (level, tier) = contribution().auto_tier().select_with_tier().unwrap();
+        assert_eq!((level.id(), tier), (Level::L4_1, Tier::High));
+
+        let (level, tier) = contribution()
+            .max_bitrate(Bitrate::from_mbps(10))
+            .auto_tier()
+            .select_with_tier()
+            .unwrap();
+        assert_eq!((level.id(), tier), (Level::L4_1, Tier::Main));
+    }
+
+    #[test]
+    fn select_decode_rate_cases() {
+        use crate::av1::{Level, LevelSelector};
+        use crate::common::{Framerate, SelectionError};
+
+        // 1080p60 fits L4.1's display rate, one hidden alt-ref per 4 shown frames takes
+        // the decode rate to 155.5M which is just below L4.1's 155.7M
+        let selector = || LevelSelector::new().width(1920).height(1080).framerate(60);
+        assert_eq!(selector().select().unwrap().id(), Level::L4_1);
+        assert_eq!(
+            selector().decode_framerate(75).select().unwrap().id(),
+            Level::L4_1
+        );
+        assert_eq!(
+            selector().decode_framerate(80).select().unwrap().id(),
+            Level::L5
+        );
+
+        // Up to L3.3 only 150 headers per second are allowed
+        let small = || LevelSelector::new().width(640).height(360).framerate(120);
+        assert_eq!(small().select().unwrap().id(), Level::L3_1);
+        assert_eq!(small().header_rate(240).select().unwrap().id(), Level::L4);
+
+        assert_eq!(
+            small()
+                .decode_framerate(Framerate::new(1, 0))
+                .select()
+                .unwrap_err(),
+            SelectionError::InvalidFramerate
+        );
+    }
+
+    #[test]
+    fn select_reference_frame_cases() {
+        use crate::av1::{Level, LevelSelector};
+        use crate::common::SelectionError;
+
+        assert_eq!(
+            LevelSelector::new()
+                .reference_frames(8)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+        assert_eq!(
+            LevelSelector::new()
+                .reference_frames(9)
+                .select()
+                .unwrap_err(),
+            SelectionError::DpbSize
+        );
+    }
+
+    #[test]
+    fn select_profile_bitrate_cases() {
+        use crate::av1::{Level, LevelSelector, Profile};
+        use crate::common::Bitrate;
+
+        let selector = |profile| {
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(30)
+                .profile(profile)
+                .max_bitrate(Bitrate::from_mbps(22))
+        };
+        // L4 carries 12 Mbit/s for Main and twice that for High
+        assert_eq!(selector(Profile::Main).select().unwrap().id(), Level::L5);
+        assert_eq!(selector(Profile::High).select().unwrap().id(), Level::L4);
+        assert_eq!(
+            selector(Profile::Professional).select().unwrap().id(),
+            Level::L4
+        );
+    }
+
+    #[test]
+    fn decoder_model() {
+        use crate::av1::{self, Level, Profile, Tier};
+        use crate::common::{Bitrate, Framerate, SelectionError};
+        use crate::hrd::{BitWriter, Frame};
+
+        let l = av1::get(Level::L4);
+        let params = l
+            .operating_parameters(Profile::Main, Tier::Main, Bitrate::from_mbps(6), false)
+            .unwrap();
+        // BufferSize is one second at the level's 12 Mbit/s and the delays add up to it,
+        // the stream drains it at its own bitrate
+        assert_eq!(params.bitrate, Bitrate::from_mbps(6));
+        assert_eq!(params.buffer_size, 12_000_000);
+        assert_eq!(params.decoder_buffer_delay, 70_000);
+        assert_eq!(params.encoder_buffer_delay, 20_000);
+
+        let info = params.decoder_model_info(Framerate::ntsc(30));
+        assert_eq!(info.buffer_delay_length_minus_1, 16);
+        assert_eq!(info.num_units_in_decoding_tick, 1001);
+        let mut w = BitWriter::new();
+        info.write(&mut w);
+        params.write(&info, &mut w);
+        assert_eq!(w.len(), 47 + 17 * 2 + 1);
+
+        let custom = params.with_delays(45_000, 45_000).unwrap();
+        assert_eq!(custom.decoder_buffer_delay, 45_000);
+        assert_eq!(params.with_delays(90_000, 1), Err(SelectionError::CpbSize));
+        assert_eq!(params.with_delays(0, 0), Err(SelectionError::CpbSize));
+
+        // 200 kbit per frame at 30 fps is exactly 6 Mbit/s, a single frame above the
+        // buffer can never arrive on time
+        let mut frames: Vec<Frame> = (0..60)
+            .map(|n| Frame::new(200_000, n * 3000, n * 3000))
+            .collect();
+        assert!(params.check(&frames).unwrap().passes());
+        frames[30].bits = 13_000_000;
+        assert!(!params.check(&frames).unwrap().passes());
+
+        assert_eq!(
+            l.operating_parameters(Profile::Main, Tier::Main, Bitrate::from_mbps(13), false),
+            Err(SelectionError::Bitrate)
+        );
+        assert!(
+            l.operating_parameters(Profile::High, Tier::Main, Bitrate::from_mbps(13), true)
+                .unwrap()
+                .low_delay_mode_flag
+        );
+        assert_eq!(
+            av1::get(Level::L3).operating_parameters(
+                Profile::Main,
+                Tier::High,
+                Bitrate::from_mbps(1),
+                false
+            ),
+            Err(SelectionError::TierUnavailable)
+        );
+    }
+
+    #[test]
+    fn max_coded_picture_bytes() {
+        use crate::av1::{self, Level, LevelSelector, Profile};
+        use crate::common::{Limit, SelectionError};
+
+        // 1080p30 decodes at 88% of L4's display rate so MinCompBasis 4 becomes 3.5
+        let l = av1::get(Level::L4);
+        assert_eq!(
+            l.max_coded_picture_bytes(1920, 1080, Profile::Main, 30),
+            Ok(1_105_920)
+        );
+        assert_eq!(
+            l.max_coded_picture_bytes(1920, 1080, Profile::High, 30),
+            Ok(2_211_840)
+        );
+        // Low framerates bottom out at a ratio of 0.8
+        assert_eq!(
+            l.max_coded_picture_bytes(1920, 1080, Profile::Main, 1),
+            Ok(4_860_000)
+        );
+
+        assert_eq!(
+            l.max_coded_picture_bytes(1 << 31, 1 << 31, Profile::Professional, 1),
+            Err(SelectionError::Overflow)
+        );
+        assert_eq!(
+            LevelSelector::new()
+                .width(1 << 31)
+                .height(1 << 31)
+                .framerate(1.0)
+                .max_picture_bytes(1)
+                .select()
+                .unwrap_err(),
+            SelectionError::Overflow
+        );
+
+        // Hidden frames count towards SpeedAdj, decoding 60 frames a second at L5.1
+        // needs a higher compression ratio than showing 30
+        let selector = |decode_framerate| {
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(30)
+                .decode_framerate(decode_framerate)
+                .max_picture_bytes(1)
+        };
+        let max_bytes = |selector: LevelSelector| {
+            let report = selector.utilization(&av1::get(Level::L5_1)).unwrap();
+            let check = report.iter().find(|c| c.limit == Limit::CodedPictureBytes);
+            check.unwrap().max.unwrap()
+        };
+        let l = av1::get(Level::L5_1);
+        assert_eq!(
+            max_bytes(selector(30)),
+            l.max_coded_picture_bytes(1920, 1080, Profile::Main, 30)
+                .unwrap()
+        );
+        assert_eq!(
+            max_bytes(selector(60)),
+            l.max_coded_picture_bytes(1920, 1080, Profile::Main, 60)
+                .unwrap()
+        );
+        assert!(max_bytes(selector(60)) < max_bytes(selector(30)));
+
+        assert_eq!(
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(30)
+                .max_picture_bytes(1_500_000)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4_1
+        );
+    }
+
+    #[test]
+    fn plan_tiles() {
+        use crate::av1::{self, Level};
+        use crate::common::{TileError, TileLayout};
+
+        // 8K is 120x68 superblocks of 64, L6 allows 16 columns and 128 tiles
+        let info = av1::get(Level::L6).plan_tiles(7680, 4320, 64).unwrap();
+        assert_eq!((info.tile_cols_log2, info.tile_rows_log2), (4, 3));
+        assert_eq!((info.layout.columns(), info.layout.rows()), (15, 8));
+        assert_eq!(info.layout.column_widths.iter().sum::<u32>(), 120);
+        assert_eq!(info.layout.row_heights.last(), Some(&5));
+
+        let info = av1::get(Level::L4).plan_tiles(1920, 1080, 64).unwrap();
+        assert_eq!((info.layout.columns(), info.layout.rows()), (8, 4));
+        let info = av1::get(Level::L4).plan_tiles(1920, 1080, 128).unwrap();
+        assert_eq!((info.layout.columns(), info.layout.rows()), (8, 3));
+
+        // 8K has to be split in two to stay within MAX_TILE_WIDTH
+        let l = av1::get(Level::L6);
+        let single = TileLayout {
+            block_size: 64,
+            column_widths: vec![120],
+            row_heights: vec![68],
+        };
+        assert_eq!(
+            l.validate_tiles(7680, 4320, &single),
+            Err(TileError::TooWide(0))
+        );
+        let halves = TileLayout {
+            block_size: 64,
+            column_widths: vec![60, 60],
+            row_heights: vec![68],
+        };
+        assert_eq!(
+            l.validate_tiles(7680, 4320, &halves),
+            Err(TileError::TooLarge(0))
+        );
+        // 257 superblocks can't be split into 4 columns of at most 4096 samples
+        assert_eq!(
+            av1::get(Level::L2).plan_tiles(16448, 64, 64).unwrap_err(),
+            TileError::TooManyTiles
+        );
+        assert_eq!(
+            l.plan_tiles(7680, 4320, 32).unwrap_err(),
+            TileError::InvalidBlockSize
+        );
+    }
+
+    #[test]
+    fn select_error_cases() {
+        use crate::av1::{LevelSelector, Tier};
+        use crate::common::{Bitrate, SelectionError};
+
+        assert_eq!(
+            LevelSelector::new()
+                .width(16384)
+                .height(8640)
+                .framerate(30.0)
+                .select()
+                .unwrap_err(),
+            SelectionError::PictureSize
+        );
+
+        assert_eq!(
+            LevelSelector::new()
+                .tier(Tier::High)
+                .max_bitrate(Bitrate::from_bps(900_000_000))
+                .select()
+                .unwrap_err(),
+            SelectionError::Bitrate
+        );
+    }
 }
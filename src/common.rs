@@ -1,6 +1,741 @@
+use std::fmt;
 use yuv::color::ChromaSampling;
 use yuv::color::Depth;
 
+/// Accessors shared by the level specifications of every codec
+pub trait LevelSpec {
+    type Level: Copy + Ord + fmt::Debug + fmt::Display;
+
+    fn id(&self) -> Self::Level;
+
+    /// Maximum number of luma samples per picture
+    fn max_luma_picture_size(&self) -> u64;
+
+    /// Maximum number of luma samples per second
+    fn max_luma_sample_rate(&self) -> u64;
+}
+
+/// Builder shared by the level selectors of every codec, this lets generic code
+/// pick a level without knowing which codec it's dealing with.
+pub trait LevelSelector: Sized {
+    type Level: Copy + Ord + fmt::Debug + fmt::Display;
+    type Profile: Copy;
+    type Specification: LevelSpec<Level = Self::Level>;
+
+    fn width(self, width: u32) -> Self;
+    fn height(self, height: u32) -> Self;
+    fn framerate(self, framerate: impl Into<Framerate>) -> Self;
+    fn clamp(self, min: Self::Level, max: Self::Level) -> Self;
+    fn max_bitrate(self, max_bitrate: Bitrate) -> Self;
+    fn profile(self, profile: Self::Profile) -> Self;
+    /// Picks the least demanding profile for the chroma format and bit depth instead
+    fn auto_profile(self, intra_only: bool) -> Self;
+    fn chroma(self, chroma: ChromaSampling) -> Self;
+    fn bit_depth(self, bit_depth: Depth) -> Self;
+    fn dpb_frames(self, frames: u32) -> Self;
+    /// Reference frames the encoder keeps, converted to the DPB frames the codec needs
+    fn reference_frames(self, frames: u32) -> Self;
+    /// Largest coded picture in bytes, usually a keyframe
+    fn max_picture_bytes(self, bytes: u64) -> Self;
+    fn headroom(self, headroom: Headroom) -> Self;
+    /// The profile selection runs with, either the fixed one or the automatic pick
+    fn resolve_profile(&self) -> Result<Self::Profile, SelectionError>;
+    fn select(self) -> Result<Self::Specification, SelectionError>;
+    /// Like `select` but also reports every level that was skipped and the limits it failed
+    fn select_explained(self) -> Explanation<Self::Specification>;
+    /// Reports how much of each limit of `level` the configuration uses
+    fn utilization(&self, level: &Self::Specification) -> Result<Vec<Check>, SelectionError>;
+}
+
+/// Frames per second as an exact fraction, so 59.94 is 60000/1001 instead of a float
+/// that gets rounded differently by every codec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Framerate {
+    num: u32,
+    den: u32,
+}
+
+impl Framerate {
+    /// `num / den` frames per second, reduced to lowest terms
+    pub const fn new(num: u32, den: u32) -> Self {
+        let (mut a, mut b) = (num, den);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        match a {
+            0 => Self { num, den },
+            gcd => Self {
+                num: num / gcd,
+                den: den / gcd,
+            },
+        }
+    }
+
+    pub const fn integer(fps: u32) -> Self {
+        Self::new(fps, 1)
+    }
+
+    /// The NTSC variant of an integer rate, `ntsc(30)` is 30000/1001 (29.97). Rates whose
+    /// numerator doesn't fit a `u32` give an invalid rate that selection rejects with
+    /// `SelectionError::InvalidFramerate`.
+    pub const fn ntsc(fps: u32) -> Self {
+        match fps.checked_mul(1000) {
+            Some(num) => Self::new(num, 1001),
+            None => Self::new(0, 0),
+        }
+    }
+
+    pub fn num(&self) -> u32 {
+        self.num
+    }
+
+    pub fn den(&self) -> u32 {
+        self.den
+    }
+
+    /// A rate is only usable when both terms are non zero
+    pub fn is_valid(&self) -> bool {
+        self.num != 0 && self.den != 0
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Smallest whole number of frames per second at or above the rate, `None` if the
+    /// denominator is zero
+    pub fn ceil(&self) -> Option<u64> {
+        (self.den != 0).then(|| (self.num as u64).div_ceil(self.den as u64))
+    }
+
+    /// Converts a per frame amount (samples, macroblocks, bits) to a per second amount,
+    /// rounded up. `None` if the denominator is zero or the result doesn't fit a `u64`
+    pub fn per_second(&self, per_frame: u64) -> Option<u64> {
+        if self.den == 0 {
+            return None;
+        }
+        let total = (per_frame as u128 * self.num as u128).div_ceil(self.den as u128);
+        u64::try_from(total).ok()
+    }
+
+    /// Twice the rate, the field rate of an interlaced frame rate. `None` on overflow
+    pub(crate) fn fields(&self) -> Option<Self> {
+        if self.den.is_multiple_of(2) {
+            Some(Framerate::new(self.num, self.den / 2))
+        } else {
+            Some(Framerate::new(self.num.checked_mul(2)?, self.den))
+        }
+    }
+}
+
+impl From<u32> for Framerate {
+    fn from(fps: u32) -> Self {
+        Framerate::integer(fps)
+    }
+}
+
+/// Fractional floats within 0.005 of an NTSC rate map to it exactly, anything else is
+/// kept to a thousandth of a frame. NaN, negative rates and rates too large for a
+/// thousandth of a frame to fit a `u32` are invalid.
+impl From<f32> for Framerate {
+    fn from(fps: f32) -> Self {
+        // Invalid instead of saturating, selection rejects these
+        if !fps.is_finite() || fps <= 0.0 || fps as f64 * 1000.0 > u32::MAX as f64 {
+            return Framerate::new(0, 0);
+        }
+        let nominal = (fps as f64 * 1.001).round();
+        if fps.fract() != 0.0 && nominal >= 1.0 {
+            let ntsc = Framerate::ntsc(nominal as u32);
+            if (ntsc.as_f64() - fps as f64).abs() < 0.005 {
+                return ntsc;
+            }
+        }
+        Framerate::new((fps as f64 * 1000.0).round() as u32, 1000)
+    }
+}
+
+impl fmt::Display for Framerate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.den {
+            1 => write!(f, "{}", self.num),
+            den => write!(f, "{}/{}", self.num, den),
+        }
+    }
+}
+
+/// A bitrate with an explicit unit. HEVC and H.264 tables are in kbit/s while AV1 is in
+/// bit/s, this keeps cross-codec code from mixing them up. Stored in bit/s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Bitrate(u64);
+
+impl Bitrate {
+    pub const fn from_bps(bps: u64) -> Self {
+        Self(bps)
+    }
+
+    pub const fn from_kbps(kbps: u64) -> Self {
+        Self(kbps.saturating_mul(1_000))
+    }
+
+    pub const fn from_mbps(mbps: u64) -> Self {
+        Self(mbps.saturating_mul(1_000_000))
+    }
+
+    pub const fn bps(&self) -> u64 {
+        self.0
+    }
+
+    /// Whole kbit/s, rounded down
+    pub const fn kbps(&self) -> u64 {
+        self.0 / 1_000
+    }
+
+    /// Whole Mbit/s, rounded down
+    pub const fn mbps(&self) -> u64 {
+        self.0 / 1_000_000
+    }
+
+    /// Applies a profile multiplier such as cpbBrVclFactor / 1000
+    pub(crate) fn scale(&self, multiplier: f64) -> Self {
+        Self((self.0 as f64 * multiplier) as u64)
+    }
+}
+
+impl fmt::Display for Bitrate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 != 0 && self.0.is_multiple_of(1_000_000) {
+            write!(f, "{} Mbit/s", self.mbps())
+        } else if self.0 != 0 && self.0.is_multiple_of(1_000) {
+            write!(f, "{} kbit/s", self.kbps())
+        } else {
+            write!(f, "{} bit/s", self.0)
+        }
+    }
+}
+
+/// Which hypothetical reference decoder a bitrate or CPB size is for. The NAL HRD also
+/// counts headers and filler data so its limits are a fixed factor above the VCL ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HrdType {
+    /// Video coding layer only, what encoders' VBV settings are checked against
+    #[default]
+    Vcl,
+    /// Whole NAL unit stream
+    Nal,
+}
+
+/// Encoder rate control settings that keep a stream within a level, `max_rate` maps to
+/// x264/x265 `--vbv-maxrate` and `buffer_size` (in bits) to `--vbv-bufsize`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vbv {
+    pub max_rate: Bitrate,
+    pub buffer_size: u64,
+}
+
+impl Vbv {
+    /// `buffer_size` in kbit, the unit x264 and x265 take it in
+    pub fn buffer_size_kbits(&self) -> u64 {
+        self.buffer_size / 1000
+    }
+}
+
+/// Reasons a selector can fail to find a level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionError {
+    /// The sample rate (or macroblock, display or header rate) is above every level
+    SampleRate,
+    /// The picture size, or one of its dimensions, is above every level
+    PictureSize,
+    /// No level allows the requested max bitrate
+    Bitrate,
+    /// No level can hold the requested number of frames in its DPB
+    DpbSize,
+    /// No level has a coded picture buffer as large as requested
+    CpbSize,
+    /// No level's minimum compression ratio allows coded pictures as large as requested
+    CodedPictureSize,
+    /// No level allows field coding for the profile
+    Interlaced,
+    /// The profile can't carry the requested chroma format or bit depth
+    UnsupportedFormat,
+    /// The width or height is zero
+    InvalidDimensions,
+    /// The framerate is zero, negative or not a number
+    InvalidFramerate,
+    /// The headroom utilization isn't above 0 and at most 1
+    InvalidHeadroom,
+    /// An intermediate value (e.g. samples per second) doesn't fit in 64 bits
+    Overflow,
+    /// None of the levels within the clamp define a bitrate for the requested tier
+    TierUnavailable,
+    /// The configuration needs a level above the clamp's max, or the clamp is empty
+    ClampRange,
+    /// The highest defined level is exceeded on more than one limit
+    ExceededAllLevels,
+}
+
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            SelectionError::SampleRate => "sample rate exceeds every level",
+            SelectionError::PictureSize => "picture size exceeds every level",
+            SelectionError::Bitrate => "no level allows the requested bitrate",
+            SelectionError::DpbSize => "no level holds the requested DPB frames",
+            SelectionError::CpbSize => "no level allows the requested CPB size",
+            SelectionError::CodedPictureSize => "no level allows the requested coded picture size",
+            SelectionError::Interlaced => "no level allows interlaced coding for the profile",
+            SelectionError::UnsupportedFormat => {
+                "profile doesn't support the chroma format or bit depth"
+            }
+            SelectionError::InvalidDimensions => "width and height must be non zero",
+            SelectionError::InvalidFramerate => "framerate must be a positive number",
+            SelectionError::InvalidHeadroom => "headroom utilization must be in (0, 1]",
+            SelectionError::Overflow => "configuration overflows 64 bit arithmetic",
+            SelectionError::TierUnavailable => "tier is not available for the clamped levels",
+            SelectionError::ClampRange => "required level is outside the clamp range",
+            SelectionError::ExceededAllLevels => "configuration exceeds every defined level",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for SelectionError {}
+
+/// Reasons a tile layout isn't allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileError {
+    /// The width or height is zero
+    InvalidDimensions,
+    /// The CTB or superblock size isn't one the codec has
+    InvalidBlockSize,
+    /// The tile sizes don't add up to the picture
+    Mismatch,
+    /// More tile columns than the level allows
+    TooManyColumns,
+    /// More tile rows than the level allows
+    TooManyRows,
+    /// More tiles than the level allows
+    TooManyTiles,
+    /// The tile column at this index is below the minimum width
+    TooNarrow(usize),
+    /// The tile row at this index is below the minimum height
+    TooShort(usize),
+    /// The tile column at this index is above the maximum width
+    TooWide(usize),
+    /// A tile in the row at this index is above the maximum area
+    TooLarge(usize),
+}
+
+impl fmt::Display for TileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TileError::InvalidDimensions => write!(f, "width and height must be non zero"),
+            TileError::InvalidBlockSize => write!(f, "unsupported CTB or superblock size"),
+            TileError::Mismatch => write!(f, "tile sizes don't cover the picture"),
+            TileError::TooManyColumns => write!(f, "too many tile columns for the level"),
+            TileError::TooManyRows => write!(f, "too many tile rows for the level"),
+            TileError::TooManyTiles => write!(f, "too many tiles for the level"),
+            TileError::TooNarrow(i) => write!(f, "tile column {} is too narrow", i),
+            TileError::TooShort(i) => write!(f, "tile row {} is too short", i),
+            TileError::TooWide(i) => write!(f, "tile column {} is too wide", i),
+            TileError::TooLarge(i) => write!(f, "tiles in row {} are too large", i),
+        }
+    }
+}
+
+impl std::error::Error for TileError {}
+
+/// A tile grid, column widths and row heights are in CTBs (HEVC) or superblocks (AV1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileLayout {
+    /// CTB or superblock size in luma samples
+    pub block_size: u32,
+    pub column_widths: Vec<u32>,
+    pub row_heights: Vec<u32>,
+}
+
+impl TileLayout {
+    /// Evenly spaced tiles the way uniform_spacing_flag lays them out, sizes differ by at
+    /// most one block
+    pub fn uniform(
+        width: u32,
+        height: u32,
+        block_size: u32,
+        columns: u32,
+        rows: u32,
+    ) -> Result<Self, TileError> {
+        if width == 0 || height == 0 {
+            return Err(TileError::InvalidDimensions);
+        }
+        if block_size == 0 {
+            return Err(TileError::InvalidBlockSize);
+        }
+        let width_blocks = width.div_ceil(block_size);
+        let height_blocks = height.div_ceil(block_size);
+        if columns == 0 || columns > width_blocks {
+            return Err(TileError::TooManyColumns);
+        }
+        if rows == 0 || rows > height_blocks {
+            return Err(TileError::TooManyRows);
+        }
+        let spacing = |blocks: u32, count: u32| -> Vec<u32> {
+            (0..count)
+                .map(|i| ((i + 1) * blocks) / count - (i * blocks) / count)
+                .collect()
+        };
+        Ok(Self {
+            block_size,
+            column_widths: spacing(width_blocks, columns),
+            row_heights: spacing(height_blocks, rows),
+        })
+    }
+
+    pub fn columns(&self) -> u32 {
+        self.column_widths.len() as u32
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.row_heights.len() as u32
+    }
+
+    pub fn tiles(&self) -> u32 {
+        self.columns() * self.rows()
+    }
+
+    /// Whether the grid covers exactly a `width` x `height` picture
+    pub(crate) fn covers(&self, width: u32, height: u32) -> bool {
+        let sum = |sizes: &[u32]| sizes.iter().map(|&s| s as u64).sum::<u64>();
+        !self.column_widths.is_empty()
+            && !self.row_heights.is_empty()
+            && !self.column_widths.contains(&0)
+            && !self.row_heights.contains(&0)
+            && sum(&self.column_widths) == width.div_ceil(self.block_size) as u64
+            && sum(&self.row_heights) == height.div_ceil(self.block_size) as u64
+    }
+}
+
+/// The limits a level puts on a configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// HEVC MaxLumaSr, luma samples per second
+    LumaSampleRate,
+    /// H.264 MaxMBPS, macroblocks per second
+    MacroblockRate,
+    /// AV1 MaxDisplayRate, luma samples per second
+    DisplayRate,
+    /// AV1 MaxHeaderRate, frame headers per second
+    HeaderRate,
+    /// AV1 MaxDecodeRate, decoded luma samples per second including hidden frames
+    DecodeRate,
+    /// Luma samples per picture
+    PictureSize,
+    /// H.264 MaxFS, macroblocks per frame
+    FrameSize,
+    /// Picture width in luma samples
+    Width,
+    /// Picture height in luma samples
+    Height,
+    /// H.264 PicWidthInMbs, sqrt(8 * MaxFS)
+    WidthMbs,
+    /// H.264 FrameHeightInMbs, sqrt(8 * MaxFS)
+    HeightMbs,
+    /// Max bitrate for the profile and tier
+    Bitrate,
+    /// Frames the decoded picture buffer can hold at the picture size
+    DpbFrames,
+    /// MaxCPB in bits for the profile, tier and HRD type
+    CpbSize,
+    /// Bytes per coded picture the minimum compression ratio allows
+    CodedPictureBytes,
+    /// H.264 field coding, a max of 1 where the level allows it and 0 where
+    /// frame_mbs_only_flag has to be 1
+    Interlaced,
+}
+
+impl Limit {
+    /// Limits that are allowed or not, headroom doesn't apply to them
+    fn is_flag(&self) -> bool {
+        matches!(self, Limit::Interlaced)
+    }
+
+    fn error(&self) -> SelectionError {
+        match self {
+            Limit::LumaSampleRate
+            | Limit::MacroblockRate
+            | Limit::DisplayRate
+            | Limit::HeaderRate
+            | Limit::DecodeRate => SelectionError::SampleRate,
+            Limit::PictureSize
+            | Limit::FrameSize
+            | Limit::Width
+            | Limit::Height
+            | Limit::WidthMbs
+            | Limit::HeightMbs => SelectionError::PictureSize,
+            Limit::Bitrate => SelectionError::Bitrate,
+            Limit::DpbFrames => SelectionError::DpbSize,
+            Limit::CpbSize => SelectionError::CpbSize,
+            Limit::CodedPictureBytes => SelectionError::CodedPictureSize,
+            Limit::Interlaced => SelectionError::Interlaced,
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limit_str = match self {
+            Limit::LumaSampleRate => "max_luma_sample_rate",
+            Limit::MacroblockRate => "max_macroblock_rate",
+            Limit::DisplayRate => "max_display_rate",
+            Limit::HeaderRate => "max_header_rate",
+            Limit::DecodeRate => "max_decode_rate",
+            Limit::PictureSize => "max_picture_size",
+            Limit::FrameSize => "max_frame_size_mbs",
+            Limit::Width => "max_width",
+            Limit::Height => "max_height",
+            Limit::WidthMbs => "max_width_mbs",
+            Limit::HeightMbs => "max_height_mbs",
+            Limit::Bitrate => "max_bit_rate",
+            Limit::DpbFrames => "max_dpb_frames",
+            Limit::CpbSize => "max_cpb_size",
+            Limit::CodedPictureBytes => "max_coded_picture_bytes",
+            Limit::Interlaced => "interlaced",
+        };
+        write!(f, "{}", limit_str)
+    }
+}
+
+/// A requested value compared against the limit of a level, `max` is `None` when the
+/// level doesn't define the limit (e.g. a High tier bitrate below level 4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Check {
+    pub limit: Limit,
+    pub requested: u64,
+    pub max: Option<u64>,
+}
+
+impl Check {
+    pub(crate) fn new(limit: Limit, requested: u64, max: Option<u64>) -> Self {
+        Self {
+            limit,
+            requested,
+            max,
+        }
+    }
+
+    pub fn passes(&self) -> bool {
+        self.max.is_some_and(|max| self.requested <= max)
+    }
+
+    /// Whether at most `fraction` of the limit is used, fractions above 1 count as 1
+    pub fn within(&self, fraction: f64) -> bool {
+        if fraction >= 1.0 || self.limit.is_flag() {
+            return self.passes();
+        }
+        self.max
+            .is_some_and(|max| self.requested as f64 <= max as f64 * fraction)
+    }
+
+    /// Fraction of the limit that is used, `None` if the level doesn't define the limit
+    pub fn utilization(&self) -> Option<f64> {
+        self.max.map(|max| self.requested as f64 / max as f64)
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.limit.is_flag() {
+            let allowed = if self.passes() {
+                "allowed"
+            } else {
+                "not allowed"
+            };
+            return write!(f, "{}: {}", self.limit, allowed);
+        }
+        match self.max {
+            Some(max) if self.requested <= max => {
+                write!(f, "{}: {} <= {}", self.limit, self.requested, max)
+            }
+            Some(max) => write!(f, "{}: {} > {}", self.limit, self.requested, max),
+            None => write!(f, "{}: {} not available", self.limit, self.requested),
+        }
+    }
+}
+
+/// How much room the selected level should leave above the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Headroom {
+    /// The lowest level the configuration fits in
+    #[default]
+    Minimum,
+    /// The lowest level where every limit is used at most this fraction (e.g. 0.8), has
+    /// to be above 0 and at most 1
+    Utilization(f64),
+    /// This many levels above the lowest one that fits, but never above the clamp
+    Levels(usize),
+}
+
+impl Headroom {
+    pub(crate) fn fraction(&self) -> Result<f64, SelectionError> {
+        match self {
+            Headroom::Utilization(fraction) if *fraction > 0.0 && *fraction <= 1.0 => Ok(*fraction),
+            Headroom::Utilization(_) => Err(SelectionError::InvalidHeadroom),
+            _ => Ok(1.0),
+        }
+    }
+}
+
+/// A level the selector walked past, with every limit it checked
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection<L> {
+    pub level: L,
+    pub checks: Vec<Check>,
+    fraction: f64,
+}
+
+impl<L> Rejection<L> {
+    /// The checks that made the selector skip this level
+    pub fn failed(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|c| !c.within(self.fraction))
+    }
+}
+
+/// Result of a selection along with the levels it skipped and why
+#[derive(Debug, Clone)]
+pub struct Explanation<S: LevelSpec> {
+    pub selected: Result<S, SelectionError>,
+    pub rejected: Vec<Rejection<S::Level>>,
+}
+
+/// Rejects inputs that make the level arithmetic meaningless
+pub(crate) fn validate(
+    width: u32,
+    height: u32,
+    framerate: Framerate,
+) -> Result<(), SelectionError> {
+    if width == 0 || height == 0 {
+        return Err(SelectionError::InvalidDimensions);
+    }
+    if !framerate.is_valid() {
+        return Err(SelectionError::InvalidFramerate);
+    }
+    Ok(())
+}
+
+/// Walks `levels` in order and returns the first one within the clamp where every check
+/// passes with the requested headroom
+pub(crate) fn select_level<S, F>(
+    levels: &[S],
+    min: Option<S::Level>,
+    max: Option<S::Level>,
+    headroom: Headroom,
+    checks: F,
+) -> Result<S, SelectionError>
+where
+    S: LevelSpec + Copy,
+    F: Fn(&S) -> Result<Vec<Check>, SelectionError>,
+{
+    explain_level(levels, min, max, headroom, checks).selected
+}
+
+/// Same walk as `select_level` but keeps the checks of every level it skipped
+pub(crate) fn explain_level<S, F>(
+    levels: &[S],
+    min: Option<S::Level>,
+    max: Option<S::Level>,
+    headroom: Headroom,
+    checks: F,
+) -> Explanation<S>
+where
+    S: LevelSpec + Copy,
+    F: Fn(&S) -> Result<Vec<Check>, SelectionError>,
+{
+    let mut rejected: Vec<Rejection<S::Level>> = Vec::new();
+
+    if let (Some(min), Some(max)) = (min, max) {
+        if min > max {
+            return Explanation {
+                selected: Err(SelectionError::ClampRange),
+                rejected,
+            };
+        }
+    }
+
+    let fraction = match headroom.fraction() {
+        Ok(fraction) => fraction,
+        Err(e) => {
+            return Explanation {
+                selected: Err(e),
+                rejected,
+            }
+        }
+    };
+    let mut tier_in_range = false;
+    let mut candidates = levels
+        .iter()
+        .filter(|l| min.is_none_or(|min| l.id() >= min));
+
+    while let Some(level) = candidates.next() {
+        let in_range = max.is_none_or(|max| level.id() <= max);
+        let level_checks = match checks(level) {
+            Ok(level_checks) => level_checks,
+            Err(e) => {
+                return Explanation {
+                    selected: Err(e),
+                    rejected,
+                }
+            }
+        };
+
+        if in_range && level_checks.iter().all(|c| c.max.is_some()) {
+            tier_in_range = true;
+        }
+
+        if level_checks.iter().all(|c| c.within(fraction)) {
+            let selected = match (in_range, tier_in_range) {
+                (true, _) => Ok(*level),
+                (false, false) => Err(SelectionError::TierUnavailable),
+                (false, true) => Err(SelectionError::ClampRange),
+            };
+
+            // Step up from the lowest fitting level, stopping at the clamp
+            let steps = match headroom {
+                Headroom::Levels(steps) if selected.is_ok() => steps,
+                _ => 0,
+            };
+            let selected = candidates
+                .take_while(|l| max.is_none_or(|max| l.id() <= max))
+                .filter(|l| checks(l).is_ok_and(|c| c.iter().all(|c| c.passes())))
+                .take(steps)
+                .last()
+                .map_or(selected, |l| Ok(*l));
+
+            return Explanation { selected, rejected };
+        }
+
+        rejected.push(Rejection {
+            level: level.id(),
+            checks: level_checks,
+            fraction,
+        });
+    }
+
+    // Nothing fits, so name what the highest level rejected
+    let selected = {
+        let mut errors = rejected.last().into_iter().flat_map(|r| {
+            r.failed().map(|c| match c.max {
+                Some(_) => c.limit.error(),
+                None => SelectionError::TierUnavailable,
+            })
+        });
+        match errors.next() {
+            Some(first) if errors.all(|e| e == first) => Err(first),
+            Some(_) => Err(SelectionError::ExceededAllLevels),
+            None => Err(SelectionError::ClampRange),
+        }
+    };
+    Explanation { selected, rejected }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProfileConstraint {
     pub max_bit_depth: Depth,
@@ -34,16 +769,23 @@ impl ProfileConstraint {
     pub fn supports_mono_chrome(&self) -> bool {
         self.chroma_formats.contains(&ChromaSampling::Monochrome)
     }
+
+    /// Whether content in this chroma format and bit depth can be carried by the profile
+    pub fn supports(&self, chroma_format: ChromaSampling, bit_depth: Depth) -> bool {
+        self.supports_chroma_format(chroma_format) && bit_depth <= self.max_bit_depth
+    }
 }
 
 pub fn yuv_bitrate(
     width: u32,
     height: u32,
-    fps: f32,
+    fps: impl Into<Framerate>,
     subsampling: ChromaSampling,
     bit_depth: Depth,
-) -> f32 {
-    let pixels = width * height;
+) -> Result<Bitrate, SelectionError> {
+    let fps = fps.into();
+    validate(width, height, fps)?;
+    let pixels = width as u64 * height as u64;
 
     // Calculate bits per pixel (bpp) for luma and chroma based on subsampling.
     let bpp = match subsampling {
@@ -66,6 +808,128 @@ pub fn yuv_bitrate(
     };
 
     // Calculate total bitrate.
-    (pixels as f32 * bpp as f32 * fps) / 1000.0
+    fps.per_second(pixels * bpp as u64)
+        .map(Bitrate::from_bps)
+        .ok_or(SelectionError::Overflow)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{Bitrate, Framerate, LevelSelector, LevelSpec, SelectionError};
+
+    fn select_4k60<S: LevelSelector>(selector: S) -> Option<S::Level> {
+        selector
+            .width(3840)
+            .height(2160)
+            .framerate(60.0)
+            .select()
+            .ok()
+            .map(|l| l.id())
+    }
+
+    #[test]
+    fn generic_selection() {
+        use crate::{av1, h264, hevc};
+
+        assert_eq!(
+            select_4k60(hevc::LevelSelector::new()),
+            Some(hevc::Level::L5_1)
+        );
+        assert_eq!(
+            select_4k60(h264::LevelSelector::new()),
+            Some(h264::Level::L5_2)
+        );
+        assert_eq!(
+            select_4k60(av1::LevelSelector::new()),
+            Some(av1::Level::L5_1)
+        );
+    }
+
+    #[test]
+    fn ntsc_selection() {
+        use crate::{av1, hevc};
+
+        // 4096x2160@59.94 is computed exactly for every codec, the float and the
+        // rational rate pick the same level
+        let select = |fps: Framerate| {
+            (
+                hevc::LevelSelector::new()
+                    .width(4096)
+                    .height(2160)
+                    .framerate(fps)
+                    .select()
+                    .map(|l| l.id()),
+                av1::LevelSelector::new()
+                    .width(4096)
+                    .height(2160)
+                    .framerate(fps)
+                    .select()
+                    .map(|l| l.id()),
+            )
+        };
+        assert_eq!(select(Framerate::ntsc(60)), select(59.94.into()));
+        assert_eq!(
+            select(Framerate::ntsc(60)),
+            (Ok(hevc::Level::L5_1), Ok(av1::Level::L5_1))
+        );
+    }
+
+    #[test]
+    fn bitrate_units() {
+        use yuv::color::{ChromaSampling, Depth};
+
+        assert_eq!(Bitrate::from_kbps(1_500), Bitrate::from_bps(1_500_000));
+        assert_eq!(Bitrate::from_mbps(60).kbps(), 60_000);
+        assert_eq!(Bitrate::from_kbps(1_500).to_string(), "1500 kbit/s");
+        assert_eq!(Bitrate::from_mbps(60).to_string(), "60 Mbit/s");
+
+        // 1080p30 8-bit 4:2:0: 1920 * 1080 * 12 * 30
+        assert_eq!(
+            super::yuv_bitrate(1920, 1080, 30, ChromaSampling::Cs420, Depth::Depth8),
+            Ok(Bitrate::from_bps(746_496_000))
+        );
+        assert_eq!(
+            super::yuv_bitrate(0, 1080, 30, ChromaSampling::Cs420, Depth::Depth8),
+            Err(SelectionError::InvalidDimensions)
+        );
+        assert_eq!(
+            super::yuv_bitrate(1920, 1080, f32::NAN, ChromaSampling::Cs420, Depth::Depth8),
+            Err(SelectionError::InvalidFramerate)
+        );
+    }
+
+    #[test]
+    fn framerate_from_float() {
+        assert_eq!(Framerate::from(59.94), Framerate::ntsc(60));
+        assert_eq!(Framerate::from(29.97), Framerate::new(30000, 1001));
+        assert_eq!(Framerate::from(23.976), Framerate::ntsc(24));
+        assert_eq!(Framerate::from(60.0), Framerate::integer(60));
+        assert_eq!(Framerate::from(12.5), Framerate::new(25, 2));
+        assert_eq!(Framerate::new(120, 2), Framerate::integer(60));
+        assert_eq!(Framerate::ntsc(60).to_string(), "60000/1001");
+        assert!(!Framerate::from(f32::NAN).is_valid());
+        assert!(!Framerate::from(-30.0).is_valid());
+        assert!(!Framerate::from(f32::INFINITY).is_valid());
+        assert!(!Framerate::from(5_000_000.0).is_valid());
+        assert!(Framerate::from(4_000_000.0).is_valid());
+        assert!(!Framerate::ntsc(5_000_000).is_valid());
+        assert_eq!(Framerate::ntsc(4_294_967).num(), 4_294_967_000);
+        assert_eq!(
+            crate::hevc::LevelSelector::new()
+                .framerate(Framerate::ntsc(u32::MAX))
+                .select()
+                .unwrap_err(),
+            SelectionError::InvalidFramerate
+        );
+    }
+
+    #[test]
+    fn framerate_exact_rates() {
+        // 1080p59.94 in macroblocks: 8160 * 60000 / 1001 = 489110.9
+        assert_eq!(Framerate::ntsc(60).per_second(8160), Some(489_111));
+        assert_eq!(Framerate::ntsc(60).ceil(), Some(60));
+        assert_eq!(Framerate::integer(60).per_second(8160), Some(489_600));
+        assert_eq!(Framerate::integer(60).per_second(u64::MAX), None);
+        assert_eq!(Framerate::new(60, 0).ceil(), None);
+    }
+}
@@ -1,4 +1,8 @@
-use crate::common::ProfileConstraint;
+use crate::common::{
+    self, Bitrate, Check, Explanation, Framerate, Headroom, HrdType, Limit, ProfileConstraint,
+    SelectionError, Vbv,
+};
+use crate::hrd::{HrdError, HrdParameters, RateMode};
 /// Implementing the H.264/AVC spec for levels
 ///
 /// https://www.itu.int/rec/T-REC-H.264
@@ -10,11 +14,24 @@ use yuv::color::Depth;
 pub struct LevelSelector {
     width: u32,
     height: u32,
-    framerate: f32,
+    framerate: Framerate,
     profile: Profile,
+    /// Set by `auto_profile`, holds whether the stream is intra only
+    auto_profile: Option<bool>,
+    chroma: ChromaSampling,
+    bit_depth: Depth,
     min_level: Option<Level>,
     max_level: Option<Level>,
-    max_bitrate: Option<u32>,
+    max_bitrate: Option<Bitrate>,
+    dpb_frames: Option<u32>,
+    /// Largest coded picture in bytes
+    max_picture_bytes: Option<u64>,
+    /// CPB size in bits the encoder uses
+    cpb_size: Option<u64>,
+    /// HRD the bitrate and CPB size are checked for
+    hrd: HrdType,
+    interlaced: bool,
+    headroom: Headroom,
 }
 
 impl Default for LevelSelector {
@@ -28,11 +45,20 @@ impl LevelSelector {
         Self {
             width: 1920,
             height: 1080,
-            framerate: 30.0,
+            framerate: Framerate::integer(30),
             profile: Profile::Main,
+            auto_profile: None,
+            chroma: ChromaSampling::Cs420,
+            bit_depth: Depth::Depth8,
             min_level: None,
             max_level: None,
             max_bitrate: None,
+            dpb_frames: None,
+            max_picture_bytes: None,
+            cpb_size: None,
+            hrd: HrdType::Vcl,
+            interlaced: false,
+            headroom: Headroom::Minimum,
         }
     }
     pub fn width(mut self, width: u32) -> Self {
@@ -43,8 +69,8 @@ impl LevelSelector {
         self.height = height;
         self
     }
-    pub fn framerate(mut self, framerate: f32) -> Self {
-        self.framerate = framerate;
+    pub fn framerate(mut self, framerate: impl Into<Framerate>) -> Self {
+        self.framerate = framerate.into();
         self
     }
     pub fn clamp(mut self, min: Level, max: Level) -> Self {
@@ -52,55 +78,247 @@ impl LevelSelector {
         self.max_level = Some(max);
         self
     }
-    pub fn max_bitrate(mut self, max_bitrate: u32) -> Self {
+    pub fn max_bitrate(mut self, max_bitrate: Bitrate) -> Self {
         self.max_bitrate = Some(max_bitrate);
         self
     }
     pub fn profile(mut self, profile: Profile) -> Self {
         self.profile = profile;
+        self.auto_profile = None;
+        self
+    }
+    /// Picks the least demanding profile that carries the chroma format and bit depth,
+    /// see `Profile::for_format`
+    pub fn auto_profile(mut self, intra_only: bool) -> Self {
+        self.auto_profile = Some(intra_only);
+        self
+    }
+    /// Chroma subsampling of the content, defaults to 4:2:0
+    pub fn chroma(mut self, chroma: ChromaSampling) -> Self {
+        self.chroma = chroma;
+        self
+    }
+    /// Bit depth of the content, defaults to 8 bit
+    pub fn bit_depth(mut self, bit_depth: Depth) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+    /// Number of frames the decoded picture buffer has to hold
+    pub fn dpb_frames(mut self, frames: u32) -> Self {
+        self.dpb_frames = Some(frames);
+        self
+    }
+    /// Number of reference frames the encoder keeps (num_ref_frames), the same as
+    /// `dpb_frames` since H.264 doesn't count the current picture
+    pub fn reference_frames(self, frames: u32) -> Self {
+        self.dpb_frames(frames)
+    }
+    /// CPB (VBV buffer) size in bits the encoder uses
+    pub fn cpb_size(mut self, bits: u64) -> Self {
+        self.cpb_size = Some(bits);
+        self
+    }
+    /// Whether `max_bitrate` and `cpb_size` are for the VCL or the NAL HRD, defaults to VCL
+    pub fn hrd(mut self, hrd: HrdType) -> Self {
+        self.hrd = hrd;
+        self
+    }
+    /// Field coded content (frame_mbs_only_flag 0) with field pairs or MBAFF. The
+    /// framerate stays the frame rate, 1080i50 is 25 frames per second.
+    pub fn interlaced(mut self, interlaced: bool) -> Self {
+        self.interlaced = interlaced;
+        self
+    }
+    /// Largest coded picture in bytes, usually a keyframe, checked against the level's
+    /// minimum compression ratio
+    pub fn max_picture_bytes(mut self, bytes: u64) -> Self {
+        self.max_picture_bytes = Some(bytes);
+        self
+    }
+    /// How much room to leave above the configuration, defaults to `Headroom::Minimum`
+    pub fn headroom(mut self, headroom: Headroom) -> Self {
+        self.headroom = headroom;
         self
     }
 
-    pub fn select(self) -> Option<LevelSpecification> {
+    /// The profile selection runs with, either the one set with `profile` or the pick
+    /// of `auto_profile`
+    pub fn resolve_profile(&self) -> Result<Profile, SelectionError> {
+        let profile = match self.auto_profile {
+            Some(intra_only) => Profile::for_format(self.chroma, self.bit_depth, intra_only),
+            None => Some(self.profile),
+        };
+        profile
+            .filter(|p| ProfileConstraint::from(p).supports(self.chroma, self.bit_depth))
+            .ok_or(SelectionError::UnsupportedFormat)
+    }
+
+    pub fn select(self) -> Result<LevelSpecification, SelectionError> {
+        common::select_level(
+            &LEVEL_DETAILS,
+            self.min_level,
+            self.max_level,
+            self.headroom,
+            |level| self.checks(level),
+        )
+    }
+
+    /// Like `select` but also reports every level that was skipped and the limits it failed
+    pub fn select_explained(self) -> Explanation<LevelSpecification> {
+        common::explain_level(
+            &LEVEL_DETAILS,
+            self.min_level,
+            self.max_level,
+            self.headroom,
+            |level| self.checks(level),
+        )
+    }
+
+    /// Reports how much of each limit of `level` the configuration uses
+    pub fn utilization(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        self.checks(level)
+    }
+
+    fn checks(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        common::validate(self.width, self.height, self.framerate)?;
+        let profile = self.resolve_profile()?;
         let width_mbs = (self.width as u64).div_ceil(16);
-        let height_mbs = (self.height as u64).div_ceil(16);
+        // FrameHeightInMbs is two fields of whole macroblocks each when field coded, the
+        // same for field pairs and MBAFF
+        let height_mbs = match self.interlaced {
+            true => 2 * (self.height as u64).div_ceil(32),
+            false => (self.height as u64).div_ceil(16),
+        };
         let frame_mbs = width_mbs * height_mbs;
-        let mb_rate = frame_mbs * self.framerate.ceil() as u64;
-
-        for level in LEVEL_DETAILS.iter() {
-            if mb_rate <= level.max_macroblock_rate
-                && frame_mbs <= level.max_frame_size_mbs as u64
-            {
-                // Check if level fits within the max specified bitrate
-                let selected = match self.max_bitrate {
-                    Some(bitrate_constraint)
-                        if level.max_bit_rate(self.profile) >= bitrate_constraint.into() =>
-                    {
-                        *level
-                    }
-                    None => *level,
-                    _ => continue,
-                };
-
-                // Clamp to min level
-                match self.min_level {
-                    Some(min) if selected.id() < min => {
-                        continue;
-                    }
-                    _ => {}
-                }
-
-                // Check if exceeds max level
-                match self.max_level {
-                    Some(max) if selected.id() > max => return None,
-                    _ => {}
-                }
-
-                return Some(selected);
-            }
+        let mb_rate = self
+            .framerate
+            .per_second(frame_mbs)
+            .ok_or(SelectionError::Overflow)?;
+
+        let mut checks = vec![
+            Check::new(
+                Limit::MacroblockRate,
+                mb_rate,
+                Some(level.max_macroblock_rate),
+            ),
+            Check::new(
+                Limit::FrameSize,
+                frame_mbs,
+                Some(level.max_frame_size_mbs as u64),
+            ),
+            Check::new(
+                Limit::WidthMbs,
+                width_mbs,
+                Some(level.max_width_mbs().into()),
+            ),
+            Check::new(
+                Limit::HeightMbs,
+                height_mbs,
+                Some(level.max_height_mbs().into()),
+            ),
+            Check::new(
+                Limit::Bitrate,
+                self.max_bitrate.unwrap_or_default().bps(),
+                Some(level.max_hrd_bit_rate(profile, self.hrd).bps()),
+            ),
+        ];
+        if let Some(bytes) = self.max_picture_bytes {
+            // Every field is a coded picture of its own
+            let (picture_height, picture_rate) = match self.interlaced {
+                true => (
+                    u32::try_from(height_mbs / 2 * 16).map_err(|_| SelectionError::Overflow)?,
+                    self.framerate.fields().ok_or(SelectionError::Overflow)?,
+                ),
+                false => (self.height, self.framerate),
+            };
+            checks.push(Check::new(
+                Limit::CodedPictureBytes,
+                bytes,
+                Some(level.max_coded_picture_bytes(
+                    self.width,
+                    picture_height,
+                    profile,
+                    picture_rate,
+                )?),
+            ));
+        }
+        if let Some(bits) = self.cpb_size {
+            checks.push(Check::new(
+                Limit::CpbSize,
+                bits,
+                Some(level.max_cpb_size(profile, self.hrd)),
+            ));
         }
+        if let Some(frames) = self.dpb_frames {
+            checks.push(Check::new(
+                Limit::DpbFrames,
+                frames.into(),
+                Some(level.dpb_frames_for(frame_mbs).into()),
+            ));
+        }
+        if self.interlaced {
+            let allowed = level.allows_interlaced(profile);
+            checks.push(Check::new(Limit::Interlaced, 1, Some(allowed.into())));
+        }
+        Ok(checks)
+    }
+}
+
+impl common::LevelSelector for LevelSelector {
+    type Level = Level;
+    type Profile = Profile;
+    type Specification = LevelSpecification;
 
-        Some(LEVEL_DETAILS[LEVEL_DETAILS.len() - 1])
+    fn width(self, width: u32) -> Self {
+        LevelSelector::width(self, width)
+    }
+    fn height(self, height: u32) -> Self {
+        LevelSelector::height(self, height)
+    }
+    fn framerate(self, framerate: impl Into<Framerate>) -> Self {
+        LevelSelector::framerate(self, framerate)
+    }
+    fn clamp(self, min: Level, max: Level) -> Self {
+        LevelSelector::clamp(self, min, max)
+    }
+    fn max_bitrate(self, max_bitrate: Bitrate) -> Self {
+        LevelSelector::max_bitrate(self, max_bitrate)
+    }
+    fn profile(self, profile: Profile) -> Self {
+        LevelSelector::profile(self, profile)
+    }
+    fn auto_profile(self, intra_only: bool) -> Self {
+        LevelSelector::auto_profile(self, intra_only)
+    }
+    fn chroma(self, chroma: ChromaSampling) -> Self {
+        LevelSelector::chroma(self, chroma)
+    }
+    fn bit_depth(self, bit_depth: Depth) -> Self {
+        LevelSelector::bit_depth(self, bit_depth)
+    }
+    fn dpb_frames(self, frames: u32) -> Self {
+        LevelSelector::dpb_frames(self, frames)
+    }
+    fn reference_frames(self, frames: u32) -> Self {
+        LevelSelector::reference_frames(self, frames)
+    }
+    fn max_picture_bytes(self, bytes: u64) -> Self {
+        LevelSelector::max_picture_bytes(self, bytes)
+    }
+    fn headroom(self, headroom: Headroom) -> Self {
+        LevelSelector::headroom(self, headroom)
+    }
+    fn resolve_profile(&self) -> Result<Profile, SelectionError> {
+        LevelSelector::resolve_profile(self)
+    }
+    fn select(self) -> Result<LevelSpecification, SelectionError> {
+        LevelSelector::select(self)
+    }
+    fn select_explained(self) -> Explanation<LevelSpecification> {
+        LevelSelector::select_explained(self)
+    }
+    fn utilization(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        LevelSelector::utilization(self, level)
     }
 }
 
@@ -120,6 +338,38 @@ pub enum Profile {
 }
 
 impl Profile {
+    /// Profiles from least to most demanding, an intra only profile comes before its
+    /// inter counterpart. Baseline, Extended and CAVLC 4:4:4 Intra restrict coding tools
+    /// rather than the format so they're never picked automatically.
+    const BY_DEMAND: [Profile; 8] = [
+        Profile::Main,
+        Profile::High,
+        Profile::High10Intra,
+        Profile::High10,
+        Profile::High422Intra,
+        Profile::High422,
+        Profile::High444Intra,
+        Profile::High444,
+    ];
+
+    fn is_intra(&self) -> bool {
+        matches!(
+            self,
+            Profile::High10Intra
+                | Profile::High422Intra
+                | Profile::High444Intra
+                | Profile::CAVLC444Intra
+        )
+    }
+
+    /// The least demanding profile that can carry the format, intra only profiles are
+    /// only considered for intra only streams
+    pub fn for_format(chroma: ChromaSampling, bit_depth: Depth, intra_only: bool) -> Option<Self> {
+        Profile::BY_DEMAND.into_iter().find(|p| {
+            (intra_only || !p.is_intra()) && ProfileConstraint::from(p).supports(chroma, bit_depth)
+        })
+    }
+
     /// Returns the cpbBrVclFactor multiplier relative to the Baseline/Main/Extended base bitrate
     pub fn bitrate_multiplier(&self) -> f64 {
         match self {
@@ -135,10 +385,9 @@ impl Profile {
 impl From<&Profile> for ProfileConstraint {
     fn from(profile: &Profile) -> Self {
         match profile {
-            Profile::Baseline | Profile::Main | Profile::Extended => ProfileConstraint::new(
-                Depth::Depth8,
-                vec![ChromaSampling::Cs420],
-            ),
+            Profile::Baseline | Profile::Main | Profile::Extended => {
+                ProfileConstraint::new(Depth::Depth8, vec![ChromaSampling::Cs420])
+            }
             Profile::High => ProfileConstraint::new(
                 Depth::Depth8,
                 vec![ChromaSampling::Monochrome, ChromaSampling::Cs420],
@@ -299,8 +548,22 @@ pub struct LevelSpecification {
     max_frame_size_mbs: u32,
     /// MaxBR in kbit/s (base value for Baseline/Main/Extended profiles)
     max_bit_rate: u32,
+    /// MaxCPB in kbit (base value for Baseline/Main/Extended profiles)
+    max_cpb_size: u32,
     /// MaxDpbMbs (max decoded picture buffer in macroblocks)
     max_dpb_mbs: u32,
+    /// MinCR
+    min_compression_ratio: u32,
+    /// MaxVmvR in luma frame samples, vertical vectors are within [-MaxVmvR, MaxVmvR - 0.25]
+    max_vmv_range: u32,
+    /// MaxMvsPer2Mb, unrestricted below level 3
+    max_mvs_per_2mb: Option<u32>,
+    /// MinLumaBiPredSize as the side of the smallest bi-predicted block
+    min_luma_bi_pred_size: Option<u32>,
+    /// direct_8x8_inference_flag has to be 1
+    direct_8x8_inference: bool,
+    /// frame_mbs_only_flag has to be 1, no field coding
+    frame_mbs_only: bool,
 }
 
 impl LevelSpecification {
@@ -316,9 +579,154 @@ impl LevelSpecification {
         self.max_frame_size_mbs
     }
 
-    /// Returns max bitrate in kbit/s, scaled by the profile's cpbBrVclFactor
-    pub fn max_bit_rate(&self, profile: Profile) -> u64 {
-        (self.max_bit_rate as f64 * profile.bitrate_multiplier()) as u64
+    /// Largest PicWidthInMbs, sqrt(MaxFS * 8)
+    pub fn max_width_mbs(&self) -> u32 {
+        (self.max_frame_size_mbs as u64 * 8).isqrt() as u32
+    }
+
+    /// Largest FrameHeightInMbs, sqrt(MaxFS * 8)
+    pub fn max_height_mbs(&self) -> u32 {
+        self.max_width_mbs()
+    }
+
+    /// Returns max bitrate scaled by the profile's cpbBrVclFactor
+    pub fn max_bit_rate(&self, profile: Profile) -> Bitrate {
+        Bitrate::from_kbps(self.max_bit_rate.into()).scale(profile.bitrate_multiplier())
+    }
+
+    /// Returns max bitrate for the VCL or NAL HRD, cpbBrNalFactor is 1.2 times
+    /// cpbBrVclFactor for every profile
+    pub fn max_hrd_bit_rate(&self, profile: Profile, hrd: HrdType) -> Bitrate {
+        let vcl = self.max_bit_rate(profile);
+        match hrd {
+            HrdType::Vcl => vcl,
+            HrdType::Nal => Bitrate::from_bps(vcl.bps() * 6 / 5),
+        }
+    }
+
+    /// Returns MaxCPB in bits scaled the same way as the bitrate
+    pub fn max_cpb_size(&self, profile: Profile, hrd: HrdType) -> u64 {
+        let vcl = (self.max_cpb_size as f64 * 1000.0 * profile.bitrate_multiplier()) as u64;
+        match hrd {
+            HrdType::Vcl => vcl,
+            HrdType::Nal => vcl * 6 / 5,
+        }
+    }
+
+    /// The largest VBV settings an encoder can use and still stay within the level
+    pub fn vbv(&self, profile: Profile, hrd: HrdType) -> Vbv {
+        Vbv {
+            max_rate: self.max_hrd_bit_rate(profile, hrd),
+            buffer_size: self.max_cpb_size(profile, hrd),
+        }
+    }
+
+    /// HRD syntax values for an encoder running at `target`, which has to be within the
+    /// level's limits for the profile and at least the smallest the syntax can signal
+    pub fn hrd_parameters(
+        &self,
+        profile: Profile,
+        hrd: HrdType,
+        target: Vbv,
+        mode: RateMode,
+    ) -> Result<HrdParameters, SelectionError> {
+        if target.max_rate > self.max_hrd_bit_rate(profile, hrd) {
+            return Err(SelectionError::Bitrate);
+        }
+        if target.buffer_size > self.max_cpb_size(profile, hrd) {
+            return Err(SelectionError::CpbSize);
+        }
+        HrdParameters::new(target, mode).map_err(|e| match e {
+            HrdError::InvalidCpbSize => SelectionError::CpbSize,
+            _ => SelectionError::Bitrate,
+        })
+    }
+
+    pub fn min_compression_ratio(&self) -> u32 {
+        self.min_compression_ratio
+    }
+
+    /// Largest coded picture in bytes at a steady framerate, 384 * Max(PicSizeInMbs,
+    /// MaxMBPS / framerate) / MinCR from A.3.1. MinCR is the same for every profile, the
+    /// profile is only taken to match the other codecs.
+    pub fn max_coded_picture_bytes(
+        &self,
+        width: u32,
+        height: u32,
+        _profile: Profile,
+        framerate: impl Into<Framerate>,
+    ) -> Result<u64, SelectionError> {
+        let framerate = framerate.into();
+        common::validate(width, height, framerate)?;
+        let frame_mbs = (width as u64).div_ceil(16) * (height as u64).div_ceil(16);
+        let mbs_per_frame = u64::try_from(
+            self.max_macroblock_rate as u128 * framerate.den() as u128 / framerate.num() as u128,
+        )
+        .map_err(|_| SelectionError::Overflow)?;
+        let bytes = frame_mbs
+            .max(mbs_per_frame)
+            .checked_mul(384)
+            .ok_or(SelectionError::Overflow)?;
+        Ok(bytes / self.min_compression_ratio as u64)
+    }
+
+    pub fn max_vmv_range(&self) -> u32 {
+        self.max_vmv_range
+    }
+
+    pub fn max_mvs_per_2mb(&self) -> Option<u32> {
+        self.max_mvs_per_2mb
+    }
+
+    /// Smallest luma block that can be bi-predicted, 8 means no sub 8x8 partitions in
+    /// bi-predicted B macroblocks. Earlier editions put this as MaxSubMbRectSize.
+    pub fn min_luma_bi_pred_size(&self) -> Option<u32> {
+        self.min_luma_bi_pred_size
+    }
+
+    /// Whether direct_8x8_inference_flag has to be 1. Baseline has no B slices so it's
+    /// never needed there while Extended needs it at every level.
+    pub fn direct_8x8_inference_required(&self, profile: Profile) -> bool {
+        match profile {
+            Profile::Baseline => false,
+            Profile::Extended => true,
+            _ => self.direct_8x8_inference,
+        }
+    }
+
+    /// Whether field coding (frame_mbs_only_flag 0) is allowed, levels 2.1 to 4.1 outside
+    /// Baseline which is progressive only
+    pub fn allows_interlaced(&self, profile: Profile) -> bool {
+        profile != Profile::Baseline && !self.frame_mbs_only
+    }
+
+    /// Checks an encoder's motion search and partition settings against the level
+    pub fn validate_tools(&self, profile: Profile, tools: &CodingTools) -> Result<(), ToolError> {
+        // A search of ±range full samples with quarter sample refinement reaches
+        // range + 0.75, which has to stay at or below the upper bound of MaxVmvR - 0.25
+        if tools.horizontal_mv_range >= MAX_HORIZONTAL_MV_RANGE {
+            return Err(ToolError::HorizontalMvRange);
+        }
+        if tools.vertical_mv_range >= self.max_vmv_range {
+            return Err(ToolError::VerticalMvRange);
+        }
+        if self
+            .max_mvs_per_2mb
+            .is_some_and(|max| tools.max_mvs_per_2mb > max)
+        {
+            return Err(ToolError::MvsPer2Mb);
+        }
+        if profile != Profile::Baseline
+            && self
+                .min_luma_bi_pred_size
+                .is_some_and(|min| tools.min_bi_pred_size < min)
+        {
+            return Err(ToolError::BiPredSize);
+        }
+        if self.direct_8x8_inference_required(profile) && !tools.direct_8x8_inference {
+            return Err(ToolError::Direct8x8Inference);
+        }
+        Ok(())
     }
 
     pub fn max_dpb_mbs(&self) -> u32 {
@@ -327,157 +735,380 @@ impl LevelSpecification {
 
     /// Returns the maximum number of decoded picture buffer frames for the given resolution
     /// Formula: min(floor(MaxDpbMbs / (ceil(w/16) * ceil(h/16))), 16)
-    pub fn max_dpb_frames(&self, width: u32, height: u32) -> u32 {
+    pub fn max_dpb_frames(&self, width: u32, height: u32) -> Result<u32, SelectionError> {
         let width_mbs = (width as u64).div_ceil(16);
         let height_mbs = (height as u64).div_ceil(16);
         let frame_mbs = width_mbs * height_mbs;
         if frame_mbs == 0 {
-            return 16;
+            return Err(SelectionError::InvalidDimensions);
         }
-        std::cmp::min((self.max_dpb_mbs as u64 / frame_mbs) as u32, 16)
+        Ok(self.dpb_frames_for(frame_mbs))
+    }
+
+    /// DPB frames for a frame of `frame_mbs` macroblocks, which has to be non zero
+    fn dpb_frames_for(&self, frame_mbs: u64) -> u32 {
+        std::cmp::min(self.max_dpb_mbs as u64 / frame_mbs, 16) as u32
     }
 }
 
+impl common::LevelSpec for LevelSpecification {
+    type Level = Level;
+
+    fn id(&self) -> Level {
+        self.id
+    }
+    /// MaxFS converted from macroblocks to luma samples
+    fn max_luma_picture_size(&self) -> u64 {
+        self.max_frame_size_mbs as u64 * 256
+    }
+    /// MaxMBPS converted from macroblocks to luma samples
+    fn max_luma_sample_rate(&self) -> u64 {
+        self.max_macroblock_rate * 256
+    }
+}
+
+/// Horizontal vectors are within [-2048, 2047.75] luma samples at every level
+pub const MAX_HORIZONTAL_MV_RANGE: u32 = 2048;
+
+/// Motion search and partition settings of an encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodingTools {
+    /// Horizontal search range in full luma samples either way
+    pub horizontal_mv_range: u32,
+    /// Vertical search range in full luma samples either way
+    pub vertical_mv_range: u32,
+    /// Most motion vectors in two consecutive macroblocks, 64 when every macroblock can
+    /// be 16 bi-predicted 4x4 blocks
+    pub max_mvs_per_2mb: u32,
+    /// Side of the smallest bi-predicted luma block, 4 or 8
+    pub min_bi_pred_size: u32,
+    /// direct_8x8_inference_flag of the SPS
+    pub direct_8x8_inference: bool,
+}
+
+impl Default for CodingTools {
+    /// A ±16 sample search with every partition size
+    fn default() -> Self {
+        Self {
+            horizontal_mv_range: 16,
+            vertical_mv_range: 16,
+            max_mvs_per_2mb: 64,
+            min_bi_pred_size: 4,
+            direct_8x8_inference: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolError {
+    /// The horizontal search range is past MAX_HORIZONTAL_MV_RANGE
+    HorizontalMvRange,
+    /// The vertical search range is past MaxVmvR
+    VerticalMvRange,
+    /// More motion vectors per two macroblocks than MaxMvsPer2Mb
+    MvsPer2Mb,
+    /// Bi-predicted blocks smaller than MinLumaBiPredSize
+    BiPredSize,
+    /// direct_8x8_inference_flag is 0 where the level needs 1
+    Direct8x8Inference,
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToolError::HorizontalMvRange => write!(f, "horizontal motion vector range too large"),
+            ToolError::VerticalMvRange => write!(f, "vertical motion vector range too large"),
+            ToolError::MvsPer2Mb => write!(f, "too many motion vectors per two macroblocks"),
+            ToolError::BiPredSize => write!(f, "bi-predicted blocks too small for the level"),
+            ToolError::Direct8x8Inference => {
+                write!(f, "level requires direct_8x8_inference_flag")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ToolError {}
+
 pub const LEVEL_DETAILS: [LevelSpecification; 20] = [
     LevelSpecification {
         id: Level::L1,
         max_macroblock_rate: 1_485,
         max_frame_size_mbs: 99,
         max_bit_rate: 64,
+        max_cpb_size: 175,
         max_dpb_mbs: 396,
+        min_compression_ratio: 2,
+        max_vmv_range: 64,
+        max_mvs_per_2mb: None,
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: false,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L1b,
         max_macroblock_rate: 1_485,
         max_frame_size_mbs: 99,
         max_bit_rate: 128,
+        max_cpb_size: 350,
         max_dpb_mbs: 396,
+        min_compression_ratio: 2,
+        max_vmv_range: 64,
+        max_mvs_per_2mb: None,
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: false,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L1_1,
         max_macroblock_rate: 3_000,
         max_frame_size_mbs: 396,
         max_bit_rate: 192,
+        max_cpb_size: 500,
         max_dpb_mbs: 900,
+        min_compression_ratio: 2,
+        max_vmv_range: 128,
+        max_mvs_per_2mb: None,
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: false,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L1_2,
         max_macroblock_rate: 6_000,
         max_frame_size_mbs: 396,
         max_bit_rate: 384,
+        max_cpb_size: 1_000,
         max_dpb_mbs: 2_376,
+        min_compression_ratio: 2,
+        max_vmv_range: 128,
+        max_mvs_per_2mb: None,
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: false,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L1_3,
         max_macroblock_rate: 11_880,
         max_frame_size_mbs: 396,
         max_bit_rate: 768,
+        max_cpb_size: 2_000,
         max_dpb_mbs: 2_376,
+        min_compression_ratio: 2,
+        max_vmv_range: 128,
+        max_mvs_per_2mb: None,
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: false,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L2,
         max_macroblock_rate: 11_880,
         max_frame_size_mbs: 396,
         max_bit_rate: 2_000,
+        max_cpb_size: 2_000,
         max_dpb_mbs: 2_376,
+        min_compression_ratio: 2,
+        max_vmv_range: 128,
+        max_mvs_per_2mb: None,
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: false,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L2_1,
         max_macroblock_rate: 19_800,
         max_frame_size_mbs: 792,
         max_bit_rate: 4_000,
+        max_cpb_size: 4_000,
         max_dpb_mbs: 4_752,
+        min_compression_ratio: 2,
+        max_vmv_range: 256,
+        max_mvs_per_2mb: None,
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: false,
+        frame_mbs_only: false,
     },
     LevelSpecification {
         id: Level::L2_2,
         max_macroblock_rate: 20_250,
         max_frame_size_mbs: 1_620,
         max_bit_rate: 4_000,
+        max_cpb_size: 4_000,
         max_dpb_mbs: 8_100,
+        min_compression_ratio: 2,
+        max_vmv_range: 256,
+        max_mvs_per_2mb: None,
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: false,
+        frame_mbs_only: false,
     },
     LevelSpecification {
         id: Level::L3,
         max_macroblock_rate: 40_500,
         max_frame_size_mbs: 1_620,
         max_bit_rate: 10_000,
+        max_cpb_size: 10_000,
         max_dpb_mbs: 8_100,
+        min_compression_ratio: 2,
+        max_vmv_range: 256,
+        max_mvs_per_2mb: Some(32),
+        min_luma_bi_pred_size: None,
+        direct_8x8_inference: true,
+        frame_mbs_only: false,
     },
     LevelSpecification {
         id: Level::L3_1,
         max_macroblock_rate: 108_000,
         max_frame_size_mbs: 3_600,
         max_bit_rate: 14_000,
+        max_cpb_size: 14_000,
         max_dpb_mbs: 18_000,
+        min_compression_ratio: 4,
+        max_vmv_range: 512,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: false,
     },
     LevelSpecification {
         id: Level::L3_2,
         max_macroblock_rate: 216_000,
         max_frame_size_mbs: 5_120,
         max_bit_rate: 20_000,
+        max_cpb_size: 20_000,
         max_dpb_mbs: 20_480,
+        min_compression_ratio: 4,
+        max_vmv_range: 512,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: false,
     },
     LevelSpecification {
         id: Level::L4,
         max_macroblock_rate: 245_760,
         max_frame_size_mbs: 8_192,
         max_bit_rate: 20_000,
+        max_cpb_size: 25_000,
         max_dpb_mbs: 32_768,
+        min_compression_ratio: 4,
+        max_vmv_range: 512,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: false,
     },
     LevelSpecification {
         id: Level::L4_1,
         max_macroblock_rate: 245_760,
         max_frame_size_mbs: 8_192,
         max_bit_rate: 50_000,
+        max_cpb_size: 62_500,
         max_dpb_mbs: 32_768,
+        min_compression_ratio: 2,
+        max_vmv_range: 512,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: false,
     },
     LevelSpecification {
         id: Level::L4_2,
         max_macroblock_rate: 522_240,
         max_frame_size_mbs: 8_704,
         max_bit_rate: 50_000,
+        max_cpb_size: 62_500,
         max_dpb_mbs: 34_816,
+        min_compression_ratio: 2,
+        max_vmv_range: 512,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L5,
         max_macroblock_rate: 589_824,
         max_frame_size_mbs: 22_080,
         max_bit_rate: 135_000,
+        max_cpb_size: 135_000,
         max_dpb_mbs: 110_400,
+        min_compression_ratio: 2,
+        max_vmv_range: 512,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L5_1,
         max_macroblock_rate: 983_040,
         max_frame_size_mbs: 36_864,
         max_bit_rate: 240_000,
+        max_cpb_size: 240_000,
         max_dpb_mbs: 184_320,
+        min_compression_ratio: 2,
+        max_vmv_range: 512,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L5_2,
         max_macroblock_rate: 2_073_600,
         max_frame_size_mbs: 36_864,
         max_bit_rate: 240_000,
+        max_cpb_size: 240_000,
         max_dpb_mbs: 184_320,
+        min_compression_ratio: 2,
+        max_vmv_range: 512,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L6,
         max_macroblock_rate: 4_177_920,
         max_frame_size_mbs: 139_264,
         max_bit_rate: 240_000,
+        max_cpb_size: 240_000,
         max_dpb_mbs: 696_320,
+        min_compression_ratio: 2,
+        max_vmv_range: 8192,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L6_1,
         max_macroblock_rate: 8_355_840,
         max_frame_size_mbs: 139_264,
         max_bit_rate: 480_000,
+        max_cpb_size: 480_000,
         max_dpb_mbs: 696_320,
+        min_compression_ratio: 2,
+        max_vmv_range: 8192,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: true,
     },
     LevelSpecification {
         id: Level::L6_2,
         max_macroblock_rate: 16_711_680,
         max_frame_size_mbs: 139_264,
         max_bit_rate: 800_000,
+        max_cpb_size: 800_000,
         max_dpb_mbs: 696_320,
+        min_compression_ratio: 2,
+        max_vmv_range: 8192,
+        max_mvs_per_2mb: Some(16),
+        min_luma_bi_pred_size: Some(8),
+        direct_8x8_inference: true,
+        frame_mbs_only: true,
     },
 ];
 
@@ -485,6 +1116,201 @@ pub const LEVEL_DETAILS: [LevelSpecification; 20] = [
 mod tests {
     use super::*;
 
+    #[test]
+    fn interlaced() {
+        // CIF fits level 1.3 progressive but field coding starts at 2.1
+        let cif = LevelSelector::new().width(352).height(288).framerate(25);
+        assert_eq!(cif.select().unwrap().id(), Level::L1_3);
+        let cif = LevelSelector::new()
+            .width(352)
+            .height(288)
+            .framerate(25)
+            .interlaced(true);
+        assert_eq!(cif.select().unwrap().id(), Level::L2_1);
+
+        // 1080i25 codes 1920x544 fields at 50 per second, MaxMBPS / 50 is 4915
+        // macroblocks at L4's MinCR of 4
+        let selector = || {
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(25)
+                .max_picture_bytes(1)
+        };
+        let max_bytes = |selector: LevelSelector| {
+            let report = selector.utilization(&get(Level::L4)).unwrap();
+            let check = report.iter().find(|c| c.limit == Limit::CodedPictureBytes);
+            check.unwrap().max.unwrap()
+        };
+        assert_eq!(max_bytes(selector().interlaced(true)), 471_840);
+        assert_eq!(max_bytes(selector()), 943_680);
+
+        // Padding a huge height to whole field pairs is a size error, not a wrap to 0
+        for interlaced in [false, true] {
+            let selector = LevelSelector::new()
+                .width(1920)
+                .height(u32::MAX)
+                .framerate(30.0)
+                .dpb_frames(4)
+                .max_picture_bytes(1)
+                .interlaced(interlaced);
+            assert_eq!(
+                selector.select().unwrap_err(),
+                SelectionError::ExceededAllLevels
+            );
+        }
+
+        // 720 lines are 46 macroblock rows as two fields of 23
+        let selector = LevelSelector::new().width(1280).height(720).framerate(30);
+        assert_eq!(selector.select().unwrap().id(), Level::L3_1);
+        let selector = LevelSelector::new()
+            .width(1280)
+            .height(720)
+            .framerate(30)
+            .interlaced(true);
+        assert_eq!(selector.select().unwrap().id(), Level::L3_2);
+
+        let selector = LevelSelector::new()
+            .width(1920)
+            .height(1080)
+            .framerate(Framerate::ntsc(30))
+            .profile(Profile::High)
+            .interlaced(true);
+        assert_eq!(selector.select().unwrap().id(), Level::L4);
+
+        // The constraint is listed for every level, not only the ones it rejects
+        let explanation = LevelSelector::new()
+            .width(352)
+            .height(288)
+            .framerate(25)
+            .interlaced(true)
+            .select_explained();
+        assert_eq!(explanation.selected.unwrap().id(), Level::L2_1);
+        let rejected = explanation.rejected.last().unwrap();
+        assert_eq!(rejected.level, Level::L2);
+        let failed: Vec<_> = rejected.failed().map(|c| c.to_string()).collect();
+        assert_eq!(failed, vec!["interlaced: not allowed"]);
+        let report = LevelSelector::new()
+            .width(352)
+            .height(288)
+            .framerate(25)
+            .interlaced(true)
+            .utilization(&get(Level::L2_1))
+            .unwrap();
+        let check = report
+            .iter()
+            .find(|c| c.limit == Limit::Interlaced)
+            .unwrap();
+        assert_eq!(check.to_string(), "interlaced: allowed");
+        // Headroom doesn't apply to an allowed or not limit
+        assert!(check.within(0.8));
+
+        // 1080 lines at 50 frames need level 4.2 which is progressive only
+        let selector = LevelSelector::new()
+            .width(1920)
+            .height(1080)
+            .framerate(50)
+            .interlaced(true);
+        assert_eq!(selector.select().unwrap_err(), SelectionError::Interlaced);
+        let selector = LevelSelector::new()
+            .profile(Profile::Baseline)
+            .interlaced(true);
+        assert_eq!(selector.select().unwrap_err(), SelectionError::Interlaced);
+
+        assert!(get(Level::L4_1).allows_interlaced(Profile::Main));
+        assert!(!get(Level::L4_2).allows_interlaced(Profile::Main));
+        assert!(!get(Level::L3).allows_interlaced(Profile::Baseline));
+    }
+
+    #[test]
+    fn motion_limits() {
+        let l3 = get(Level::L3);
+        assert_eq!(l3.max_vmv_range(), 256);
+        assert_eq!(l3.max_mvs_per_2mb(), Some(32));
+        assert_eq!(l3.min_luma_bi_pred_size(), None);
+        let l4 = get(Level::L4);
+        assert_eq!(l4.max_vmv_range(), 512);
+        assert_eq!(l4.max_mvs_per_2mb(), Some(16));
+        assert_eq!(l4.min_luma_bi_pred_size(), Some(8));
+        assert_eq!(get(Level::L1b).max_vmv_range(), 64);
+        assert_eq!(get(Level::L2_2).max_mvs_per_2mb(), None);
+
+        assert!(!get(Level::L2_2).direct_8x8_inference_required(Profile::Main));
+        assert!(l3.direct_8x8_inference_required(Profile::High));
+        assert!(!l4.direct_8x8_inference_required(Profile::Baseline));
+        assert!(get(Level::L1).direct_8x8_inference_required(Profile::Extended));
+    }
+
+    #[test]
+    fn validate_tools() {
+        let tools = CodingTools::default();
+        assert_eq!(get(Level::L2).validate_tools(Profile::Main, &tools), Ok(()));
+        assert_eq!(
+            get(Level::L3).validate_tools(Profile::Main, &tools),
+            Err(ToolError::MvsPer2Mb)
+        );
+
+        let tools = CodingTools {
+            max_mvs_per_2mb: 16,
+            ..Default::default()
+        };
+        assert_eq!(
+            get(Level::L4).validate_tools(Profile::High, &tools),
+            Err(ToolError::BiPredSize)
+        );
+        // Baseline has no B slices
+        assert_eq!(
+            get(Level::L4).validate_tools(Profile::Baseline, &tools),
+            Ok(())
+        );
+
+        let tools = CodingTools {
+            max_mvs_per_2mb: 16,
+            min_bi_pred_size: 8,
+            direct_8x8_inference: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            get(Level::L4).validate_tools(Profile::High, &tools),
+            Err(ToolError::Direct8x8Inference)
+        );
+
+        let tools = CodingTools {
+            vertical_mv_range: 64,
+            ..Default::default()
+        };
+        assert_eq!(
+            get(Level::L1).validate_tools(Profile::Main, &tools),
+            Err(ToolError::VerticalMvRange)
+        );
+        assert_eq!(
+            get(Level::L1_1).validate_tools(Profile::Main, &tools),
+            Ok(())
+        );
+        let tools = CodingTools {
+            horizontal_mv_range: 2048,
+            ..Default::default()
+        };
+        assert_eq!(
+            get(Level::L1_1).validate_tools(Profile::Main, &tools),
+            Err(ToolError::HorizontalMvRange)
+        );
+
+        // Level 6 widens MaxVmvR to 8192
+        let tools = CodingTools {
+            vertical_mv_range: 1024,
+            max_mvs_per_2mb: 16,
+            min_bi_pred_size: 8,
+            ..Default::default()
+        };
+        assert_eq!(
+            get(Level::L5_2).validate_tools(Profile::High, &tools),
+            Err(ToolError::VerticalMvRange)
+        );
+        assert_eq!(get(Level::L6).validate_tools(Profile::High, &tools), Ok(()));
+        assert_eq!(get(Level::L6_2).max_vmv_range(), 8192);
+    }
+
     #[test]
     fn level_idc() {
         assert_eq!(Level::L3_1, Level::from(31));
@@ -501,13 +1327,16 @@ mod tests {
         let l = get(Level::L5_1);
         assert_eq!(l.id(), Level::L5_1);
         // Base bitrate for Main profile: 240,000 kbit/s
-        assert_eq!(l.max_bit_rate(Profile::Main), 240_000);
+        assert_eq!(l.max_bit_rate(Profile::Main), Bitrate::from_kbps(240_000));
         // High: 240,000 * 1.25 = 300,000
-        assert_eq!(l.max_bit_rate(Profile::High), 300_000);
+        assert_eq!(l.max_bit_rate(Profile::High), Bitrate::from_kbps(300_000));
         // High10: 240,000 * 3.0 = 720,000
-        assert_eq!(l.max_bit_rate(Profile::High10), 720_000);
+        assert_eq!(l.max_bit_rate(Profile::High10), Bitrate::from_kbps(720_000));
         // High422: 240,000 * 4.0 = 960,000
-        assert_eq!(l.max_bit_rate(Profile::High422), 960_000);
+        assert_eq!(
+            l.max_bit_rate(Profile::High422),
+            Bitrate::from_kbps(960_000)
+        );
     }
 
     #[test]
@@ -515,15 +1344,43 @@ mod tests {
         // L4, 1920x1080: ceil(1920/16)*ceil(1080/16) = 120*68 = 8160
         // MaxDpbMbs=32768, 32768/8160 = 4
         let l = get(Level::L4);
-        assert_eq!(l.max_dpb_frames(1920, 1080), 4);
+        assert_eq!(l.max_dpb_frames(1920, 1080), Ok(4));
 
         // L4, 1280x720: ceil(1280/16)*ceil(720/16) = 80*45 = 3600
         // MaxDpbMbs=32768, 32768/3600 = 9
-        assert_eq!(l.max_dpb_frames(1280, 720), 9);
+        assert_eq!(l.max_dpb_frames(1280, 720), Ok(9));
 
         // L5.1, 1920x1080: MaxDpbMbs=184320, 184320/8160 = 22 -> capped at 16
         let l = get(Level::L5_1);
-        assert_eq!(l.max_dpb_frames(1920, 1080), 16);
+        assert_eq!(l.max_dpb_frames(1920, 1080), Ok(16));
+
+        assert_eq!(
+            l.max_dpb_frames(0, 0),
+            Err(SelectionError::InvalidDimensions)
+        );
+    }
+
+    #[test]
+    fn utilization() {
+        // 1080p30 with 8 DPB frames at L4: 244800 of 245760 MB/s and 8 of 4 frames
+        let report = LevelSelector::new()
+            .width(1920)
+            .height(1080)
+            .framerate(30.0)
+            .dpb_frames(8)
+            .utilization(&get(Level::L4))
+            .unwrap();
+
+        let mb_rate = report
+            .iter()
+            .find(|c| c.limit == Limit::MacroblockRate)
+            .unwrap();
+        assert_eq!(mb_rate.requested, 244_800);
+        assert!(mb_rate.passes());
+
+        let dpb = report.iter().find(|c| c.limit == Limit::DpbFrames).unwrap();
+        assert_eq!(dpb.utilization(), Some(2.0));
+        assert!(!dpb.passes());
     }
 
     #[test]
@@ -606,15 +1463,271 @@ mod tests {
             Level::L5
         );
 
-        // Max clamp returns None when exceeded
+        // Max clamp returns an error when exceeded
         // 1920x1080@30 selects L4, but max clamp to L3.2 means it exceeds
-        assert!(LevelSelector::new()
-            .width(1920)
-            .height(1080)
-            .framerate(30.0)
-            .profile(Profile::Main)
-            .clamp(Level::L1, Level::L3_2)
-            .select()
-            .is_none());
+        assert_eq!(
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(30.0)
+                .profile(Profile::Main)
+                .clamp(Level::L1, Level::L3_2)
+                .select()
+                .unwrap_err(),
+            SelectionError::ClampRange
+        );
+    }
+
+    #[test]
+    fn select_format_cases() {
+        // Baseline only carries 8 bit 4:2:0
+        assert_eq!(
+            LevelSelector::new()
+                .profile(Profile::Baseline)
+                .chroma(ChromaSampling::Monochrome)
+                .select()
+                .unwrap_err(),
+            SelectionError::UnsupportedFormat
+        );
+
+        assert_eq!(
+            LevelSelector::new()
+                .profile(Profile::High422)
+                .chroma(ChromaSampling::Cs422)
+                .bit_depth(Depth::Depth10)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+    }
+
+    #[test]
+    fn auto_profile_cases() {
+        let profile = |chroma, bit_depth, intra_only| {
+            LevelSelector::new()
+                .chroma(chroma)
+                .bit_depth(bit_depth)
+                .auto_profile(intra_only)
+                .resolve_profile()
+        };
+
+        assert_eq!(
+            profile(ChromaSampling::Cs420, Depth::Depth8, false),
+            Ok(Profile::Main)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Monochrome, Depth::Depth8, false),
+            Ok(Profile::High)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs420, Depth::Depth10, false),
+            Ok(Profile::High10)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs420, Depth::Depth10, true),
+            Ok(Profile::High10Intra)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs422, Depth::Depth8, false),
+            Ok(Profile::High422)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs444, Depth::Depth12, true),
+            Ok(Profile::High444Intra)
+        );
+
+        // The picked profile's bitrate multiplier feeds into the level choice
+        assert_eq!(
+            LevelSelector::new()
+                .chroma(ChromaSampling::Cs422)
+                .bit_depth(Depth::Depth10)
+                .auto_profile(false)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+    }
+
+    #[test]
+    fn select_dimension_cases() {
+        // L3.1 MaxFS is 3600, sqrt(28800) = 169
+        assert_eq!(get(Level::L3_1).max_width_mbs(), 169);
+        assert_eq!(get(Level::L3_1).max_height_mbs(), 169);
+
+        // 3840x240 is 240x15 = 3600 MBs, within L3.1's MaxFS but too wide until L4's 256
+        assert_eq!(
+            LevelSelector::new()
+                .width(3840)
+                .height(240)
+                .framerate(25.0)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+
+        // A vertical video wall is held back by its height the same way
+        assert_eq!(
+            LevelSelector::new()
+                .width(240)
+                .height(3840)
+                .framerate(25.0)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+    }
+
+    #[test]
+    fn select_reference_frame_cases() {
+        // 1080p is 8160 MBs, L4's MaxDpbMbs of 32768 holds 4 of them
+        let selector = || {
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(30.0)
+        };
+        assert_eq!(
+            selector().reference_frames(4).select().unwrap().id(),
+            Level::L4
+        );
+        assert_eq!(
+            selector().reference_frames(8).select().unwrap().id(),
+            Level::L5
+        );
+        assert_eq!(
+            selector().reference_frames(17).select().unwrap_err(),
+            SelectionError::DpbSize
+        );
+    }
+
+    #[test]
+    fn max_cpb_size() {
+        let l = get(Level::L4);
+        assert_eq!(l.max_cpb_size(Profile::Main, HrdType::Vcl), 25_000_000);
+        assert_eq!(l.max_cpb_size(Profile::High, HrdType::Vcl), 31_250_000);
+        assert_eq!(l.max_cpb_size(Profile::High, HrdType::Nal), 37_500_000);
+        assert_eq!(
+            l.max_hrd_bit_rate(Profile::High, HrdType::Nal),
+            Bitrate::from_kbps(30_000)
+        );
+
+        let vbv = get(Level::L4_1).vbv(Profile::High, HrdType::Vcl);
+        assert_eq!(vbv.max_rate, Bitrate::from_kbps(62_500));
+        assert_eq!(vbv.buffer_size_kbits(), 78_125);
+    }
+
+    #[test]
+    fn select_cpb_cases() {
+        // L4 High holds 31.25 Mbit of CPB, 37.5 Mbit for the NAL HRD
+        let selector = || {
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(30.0)
+                .profile(Profile::High)
+        };
+        assert_eq!(
+            selector().cpb_size(30_000_000).select().unwrap().id(),
+            Level::L4
+        );
+        assert_eq!(
+            selector()
+                .cpb_size(30_000_000)
+                .hrd(HrdType::Nal)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+        assert_eq!(
+            selector().cpb_size(40_000_000).select().unwrap().id(),
+            Level::L4_1
+        );
+        assert_eq!(
+            selector().cpb_size(1_200_000_000).select().unwrap_err(),
+            SelectionError::CpbSize
+        );
+    }
+
+    #[test]
+    fn max_coded_picture_bytes() {
+        // MaxMBPS / 30 = 8192 MBs at L4's MinCR of 4
+        assert_eq!(
+            get(Level::L4).max_coded_picture_bytes(1920, 1080, Profile::High, 30),
+            Ok(786_432)
+        );
+        assert_eq!(get(Level::L4_1).min_compression_ratio(), 2);
+        assert_eq!(
+            get(Level::L4).max_coded_picture_bytes(0, 1080, Profile::High, 30),
+            Err(SelectionError::InvalidDimensions)
+        );
+        // Far below one frame per second the byte count grows past 64 bits
+        assert_eq!(
+            get(Level::L6_2).max_coded_picture_bytes(
+                1920,
+                1080,
+                Profile::High,
+                Framerate::new(1, u32::MAX)
+            ),
+            Err(SelectionError::Overflow)
+        );
+
+        let selector = || {
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(30.0)
+        };
+        assert_eq!(
+            selector().max_picture_bytes(700_000).select().unwrap().id(),
+            Level::L4
+        );
+        assert_eq!(
+            selector()
+                .max_picture_bytes(1_000_000)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4_1
+        );
+    }
+
+    #[test]
+    fn select_error_cases() {
+        // 7680x4320@120: frame_mbs = 480*270 = 129600, fits L6.2 MaxFS
+        // mb_rate = 15552000 also fits, at 240fps only the rate is exceeded
+        assert_eq!(
+            LevelSelector::new()
+                .width(7680)
+                .height(4320)
+                .framerate(240.0)
+                .select()
+                .unwrap_err(),
+            SelectionError::SampleRate
+        );
+
+        // 1080p30 at 1 Gbit/s is beyond L6.2 Main (800 Mbit/s)
+        assert_eq!(
+            LevelSelector::new()
+                .max_bitrate(Bitrate::from_kbps(1_000_000))
+                .select()
+                .unwrap_err(),
+            SelectionError::Bitrate
+        );
+
+        // 16K is too large and too fast for L6.2
+        assert_eq!(
+            LevelSelector::new()
+                .width(15360)
+                .height(8640)
+                .framerate(60.0)
+                .select()
+                .unwrap_err(),
+            SelectionError::ExceededAllLevels
+        );
     }
 }
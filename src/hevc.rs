@@ -1,4 +1,8 @@
-use crate::common::ProfileConstraint;
+use crate::common::{
+    self, Bitrate, Check, Explanation, Framerate, Headroom, HrdType, Limit, ProfileConstraint,
+    SelectionError, TileError, TileLayout, Vbv,
+};
+use crate::hrd::{HrdError, HrdParameters, RateMode};
 /// Implementing the HEVC spec for levels
 ///
 /// https://itu.int/rec/T-REC-H.265-202309-I/en
@@ -11,12 +15,36 @@ pub struct LevelSelector {
     // Constraints
     width: u32,
     height: u32,
-    framerate: f32,
+    framerate: Framerate,
     tier: Tier,
+    /// Set by `auto_tier`, lets selection fall back to High tier per level
+    auto_tier: bool,
     profile: Profile,
+    /// Set by `auto_profile`, holds whether the stream is intra only
+    auto_profile: Option<bool>,
+    chroma: ChromaSampling,
+    bit_depth: Depth,
     min_level: Option<Level>,
     max_level: Option<Level>,
-    max_bitrate: Option<u32>,
+    max_bitrate: Option<Bitrate>,
+    dpb_frames: Option<u32>,
+    /// Largest coded picture in bytes
+    max_picture_bytes: Option<u64>,
+    /// CPB size in bits the encoder uses
+    cpb_size: Option<u64>,
+    /// HRD the bitrate and CPB size are checked for
+    hrd: HrdType,
+    /// sps_curr_pic_ref_enabled_flag, SCC profiles get a larger DPB with it
+    curr_pic_ref_enabled: bool,
+    /// field_seq_flag, every field is coded as its own picture
+    interlaced: bool,
+    headroom: Headroom,
+}
+
+impl Default for LevelSelector {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LevelSelector {
@@ -25,13 +53,24 @@ impl LevelSelector {
             // Define default behaviour if no constraints are set
             width: 1920,
             height: 1080,
-            framerate: 30.0,
+            framerate: Framerate::integer(30),
             tier: Tier::Main,
+            auto_tier: false,
             profile: Profile::Main,
+            auto_profile: None,
+            chroma: ChromaSampling::Cs420,
+            bit_depth: Depth::Depth8,
             // Ignore if not set
             min_level: None,
             max_level: None,
             max_bitrate: None,
+            dpb_frames: None,
+            max_picture_bytes: None,
+            cpb_size: None,
+            hrd: HrdType::Vcl,
+            curr_pic_ref_enabled: false,
+            interlaced: false,
+            headroom: Headroom::Minimum,
         }
     }
     pub fn width(mut self, width: u32) -> Self {
@@ -42,8 +81,8 @@ impl LevelSelector {
         self.height = height;
         self
     }
-    pub fn framerate(mut self, framerate: f32) -> Self {
-        self.framerate = framerate;
+    pub fn framerate(mut self, framerate: impl Into<Framerate>) -> Self {
+        self.framerate = framerate.into();
         self
     }
     pub fn clamp(mut self, min: Level, max: Level) -> Self {
@@ -51,57 +90,303 @@ impl LevelSelector {
         self.max_level = Some(max);
         self
     }
-    pub fn max_bitrate(mut self, max_bitrate: u32) -> Self {
+    pub fn max_bitrate(mut self, max_bitrate: Bitrate) -> Self {
         self.max_bitrate = Some(max_bitrate);
         self
     }
     pub fn tier(mut self, tier: Tier) -> Self {
         self.tier = tier;
+        self.auto_tier = false;
+        self
+    }
+    /// Lets selection pick the tier, each level is tried at Main tier and then at High
+    /// tier before moving up, so L4.1 High is preferred over L5 Main
+    pub fn auto_tier(mut self) -> Self {
+        self.auto_tier = true;
         self
     }
     pub fn profile(mut self, profile: Profile) -> Self {
         self.profile = profile;
+        self.auto_profile = None;
+        self
+    }
+    /// Picks the least demanding profile that carries the chroma format and bit depth,
+    /// see `Profile::for_format`
+    pub fn auto_profile(mut self, intra_only: bool) -> Self {
+        self.auto_profile = Some(intra_only);
+        self
+    }
+    /// Chroma subsampling of the content, defaults to 4:2:0
+    pub fn chroma(mut self, chroma: ChromaSampling) -> Self {
+        self.chroma = chroma;
+        self
+    }
+    /// Bit depth of the content, defaults to 8 bit
+    pub fn bit_depth(mut self, bit_depth: Depth) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+    /// Number of frames the decoded picture buffer has to hold
+    pub fn dpb_frames(mut self, frames: u32) -> Self {
+        self.dpb_frames = Some(frames);
+        self
+    }
+    /// Number of reference frames the encoder keeps, the DPB also holds the current
+    /// picture so this needs one more frame than `dpb_frames`
+    pub fn reference_frames(self, frames: u32) -> Self {
+        self.dpb_frames(frames.saturating_add(1))
+    }
+    /// Whether the current picture is used as a reference (sps_curr_pic_ref_enabled_flag),
+    /// with an SCC profile this adds a DPB slot
+    pub fn curr_pic_ref_enabled(mut self, enabled: bool) -> Self {
+        self.curr_pic_ref_enabled = enabled;
+        self
+    }
+    /// Field coded content (field_seq_flag), each field is a picture of half the height
+    /// at twice the rate. The framerate stays the frame rate, 1080i50 is 25 frames per
+    /// second.
+    pub fn interlaced(mut self, interlaced: bool) -> Self {
+        self.interlaced = interlaced;
+        self
+    }
+    /// CPB (VBV buffer) size in bits the encoder uses
+    pub fn cpb_size(mut self, bits: u64) -> Self {
+        self.cpb_size = Some(bits);
+        self
+    }
+    /// Whether `max_bitrate` and `cpb_size` are for the VCL or the NAL HRD, defaults to VCL
+    pub fn hrd(mut self, hrd: HrdType) -> Self {
+        self.hrd = hrd;
+        self
+    }
+    /// Largest coded picture in bytes, usually a keyframe, checked against the level's
+    /// minimum compression ratio
+    pub fn max_picture_bytes(mut self, bytes: u64) -> Self {
+        self.max_picture_bytes = Some(bytes);
+        self
+    }
+    /// How much room to leave above the configuration, defaults to `Headroom::Minimum`
+    pub fn headroom(mut self, headroom: Headroom) -> Self {
+        self.headroom = headroom;
         self
     }
 
-    pub fn select(self) -> Option<LevelSpecification> {
-        let samplerate = (self.width as u64) * (self.height as u64) * self.framerate.ceil() as u64;
-
-        for level in LEVEL_DETAILS.iter() {
-            if samplerate <= level.max_luma_sample_rate {
-                // Check if level fits within the max specified bitrate
-                let selected = match (
-                    self.max_bitrate,
-                    level.max_bit_rate(self.profile, self.tier),
-                ) {
-                    (Some(bitrate_constraint), Some(level_max_bitrate))
-                        if level_max_bitrate >= bitrate_constraint.into() =>
-                    {
-                        *level
-                    }
-                    (None, Some(_)) => *level,
-                    _ => continue,
-                };
+    /// The profile selection runs with, either the one set with `profile` or the pick
+    /// of `auto_profile`
+    pub fn resolve_profile(&self) -> Result<Profile, SelectionError> {
+        let profile = match self.auto_profile {
+            Some(intra_only) => Profile::for_format(self.chroma, self.bit_depth, intra_only),
+            None => Some(self.profile),
+        };
+        profile
+            .filter(|p| ProfileConstraint::from(p).supports(self.chroma, self.bit_depth))
+            .ok_or(SelectionError::UnsupportedFormat)
+    }
+
+    pub fn select(self) -> Result<LevelSpecification, SelectionError> {
+        common::select_level(
+            SELECTABLE_LEVELS,
+            self.min_level,
+            self.max_level,
+            self.headroom,
+            |level| self.checks(level),
+        )
+    }
 
-                // Clamp to min level
-                match self.min_level {
-                    Some(min) if selected.id() < min => {
-                        continue;
-                    }
-                    _ => {}
-                }
-
-                // Check if exceeds max level
-                match self.max_level {
-                    Some(max) if selected.id() > max => return None,
-                    _ => {}
-                }
-
-                return Some(selected);
-            }
+    /// Like `select` but also reports every level that was skipped and the limits it failed
+    pub fn select_explained(self) -> Explanation<LevelSpecification> {
+        common::explain_level(
+            SELECTABLE_LEVELS,
+            self.min_level,
+            self.max_level,
+            self.headroom,
+            |level| self.checks(level),
+        )
+    }
+
+    /// Reports how much of each limit of `level` the configuration uses
+    pub fn utilization(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        self.checks(level)
+    }
+
+    /// Like `select` but also returns the tier the level was selected at, which is only
+    /// different from the configured tier with `auto_tier`
+    pub fn select_with_tier(self) -> Result<(LevelSpecification, Tier), SelectionError> {
+        let level = common::select_level(
+            SELECTABLE_LEVELS,
+            self.min_level,
+            self.max_level,
+            self.headroom,
+            |level| self.checks(level),
+        )?;
+        Ok((level, self.tier_for(&level)?))
+    }
+
+    /// The tier `level` is checked at, with `auto_tier` that's Main unless only High tier
+    /// fits the configuration with the requested headroom
+    fn tier_for(&self, level: &LevelSpecification) -> Result<Tier, SelectionError> {
+        if !self.auto_tier {
+            return Ok(self.tier);
+        }
+        let fraction = self.headroom.fraction()?;
+        if self
+            .tier_checks(level, Tier::Main)?
+            .iter()
+            .all(|c| c.within(fraction))
+        {
+            return Ok(Tier::Main);
+        }
+        if level.max_bit_rate_high.is_some() {
+            Ok(Tier::High)
+        } else {
+            Ok(Tier::Main)
         }
+    }
+
+    fn checks(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        self.tier_checks(level, self.tier_for(level)?)
+    }
+
+    fn tier_checks(
+        &self,
+        level: &LevelSpecification,
+        tier: Tier,
+    ) -> Result<Vec<Check>, SelectionError> {
+        common::validate(self.width, self.height, self.framerate)?;
+        let profile = self.resolve_profile()?;
+        let (height, framerate) = match self.interlaced {
+            true => (
+                self.height.div_ceil(2),
+                self.framerate.fields().ok_or(SelectionError::Overflow)?,
+            ),
+            false => (self.height, self.framerate),
+        };
+        let samplerate = framerate
+            .per_second((self.width as u64) * (height as u64))
+            .ok_or(SelectionError::Overflow)?;
 
-        Some(LEVEL_DETAILS[LEVEL_DETAILS.len() - 1])
+        let mut checks = vec![
+            Check::new(
+                Limit::LumaSampleRate,
+                samplerate,
+                Some(level.max_luma_sample_rate),
+            ),
+            Check::new(
+                Limit::PictureSize,
+                (self.width as u64) * (height as u64),
+                Some(level.max_luma_picture_size.into()),
+            ),
+            Check::new(
+                Limit::Width,
+                self.width.into(),
+                Some(level.max_width().into()),
+            ),
+            Check::new(
+                Limit::Height,
+                height.into(),
+                Some(level.max_height().into()),
+            ),
+            Check::new(
+                Limit::Bitrate,
+                self.max_bitrate.unwrap_or_default().bps(),
+                level
+                    .max_hrd_bit_rate(profile, tier, self.hrd)
+                    .map(|b| b.bps()),
+            ),
+        ];
+        if let Some(bytes) = self.max_picture_bytes {
+            let max_bytes =
+                match level.max_coded_picture_bytes(self.width, height, profile, tier, framerate) {
+                    Ok(max_bytes) => Some(max_bytes),
+                    Err(SelectionError::TierUnavailable) => None,
+                    Err(e) => return Err(e),
+                };
+            checks.push(Check::new(Limit::CodedPictureBytes, bytes, max_bytes));
+        }
+        if let Some(bits) = self.cpb_size {
+            checks.push(Check::new(
+                Limit::CpbSize,
+                bits,
+                level.max_cpb_size(profile, tier, self.hrd),
+            ));
+        }
+        if let Some(frames) = self.dpb_frames {
+            let max_dpb_size = match level.max_decoder_picture_buffer_size(
+                self.width,
+                height,
+                profile,
+                self.curr_pic_ref_enabled,
+            ) {
+                Ok(size) => size,
+                // The picture size check above already rejects the level
+                Err(SelectionError::PictureSize) => return Ok(checks),
+                Err(e) => return Err(e),
+            };
+            checks.push(Check::new(
+                Limit::DpbFrames,
+                frames.into(),
+                Some(max_dpb_size.into()),
+            ));
+        }
+        Ok(checks)
+    }
+}
+
+impl common::LevelSelector for LevelSelector {
+    type Level = Level;
+    type Profile = Profile;
+    type Specification = LevelSpecification;
+
+    fn width(self, width: u32) -> Self {
+        LevelSelector::width(self, width)
+    }
+    fn height(self, height: u32) -> Self {
+        LevelSelector::height(self, height)
+    }
+    fn framerate(self, framerate: impl Into<Framerate>) -> Self {
+        LevelSelector::framerate(self, framerate)
+    }
+    fn clamp(self, min: Level, max: Level) -> Self {
+        LevelSelector::clamp(self, min, max)
+    }
+    fn max_bitrate(self, max_bitrate: Bitrate) -> Self {
+        LevelSelector::max_bitrate(self, max_bitrate)
+    }
+    fn profile(self, profile: Profile) -> Self {
+        LevelSelector::profile(self, profile)
+    }
+    fn auto_profile(self, intra_only: bool) -> Self {
+        LevelSelector::auto_profile(self, intra_only)
+    }
+    fn chroma(self, chroma: ChromaSampling) -> Self {
+        LevelSelector::chroma(self, chroma)
+    }
+    fn bit_depth(self, bit_depth: Depth) -> Self {
+        LevelSelector::bit_depth(self, bit_depth)
+    }
+    fn dpb_frames(self, frames: u32) -> Self {
+        LevelSelector::dpb_frames(self, frames)
+    }
+    fn reference_frames(self, frames: u32) -> Self {
+        LevelSelector::reference_frames(self, frames)
+    }
+    fn max_picture_bytes(self, bytes: u64) -> Self {
+        LevelSelector::max_picture_bytes(self, bytes)
+    }
+    fn headroom(self, headroom: Headroom) -> Self {
+        LevelSelector::headroom(self, headroom)
+    }
+    fn resolve_profile(&self) -> Result<Profile, SelectionError> {
+        LevelSelector::resolve_profile(self)
+    }
+    fn select(self) -> Result<LevelSpecification, SelectionError> {
+        LevelSelector::select(self)
+    }
+    fn select_explained(self) -> Explanation<LevelSpecification> {
+        LevelSelector::select_explained(self)
+    }
+    fn utilization(&self, level: &LevelSpecification) -> Result<Vec<Check>, SelectionError> {
+        LevelSelector::utilization(self, level)
     }
 }
 
@@ -134,44 +419,135 @@ pub enum Profile {
     Main444,
     Main444_16Intra,
     Main444_16IntraHighThroughput,
+    ScreenExtendedMain,
+    ScreenExtendedMain10,
+    ScreenExtendedMain444,
+    ScreenExtendedMain444_10,
 }
 
 impl Profile {
-    pub fn bitrate_multiplier(&self) -> f64 {
+    /// Profiles from least to most demanding, the ones after `Main444` are intra only
+    const BY_DEMAND: [Profile; 7] = [
+        Profile::Main,
+        Profile::Main10,
+        Profile::Main12,
+        Profile::Main422_10,
+        Profile::Main444,
+        Profile::Main444_16Intra,
+        Profile::Main444_16IntraHighThroughput,
+    ];
+
+    fn is_intra(&self) -> bool {
+        matches!(
+            self,
+            Profile::Main444_16Intra | Profile::Main444_16IntraHighThroughput
+        )
+    }
+
+    /// The least demanding profile that can carry the format, intra only profiles are
+    /// only considered for intra only streams
+    pub fn for_format(chroma: ChromaSampling, bit_depth: Depth, intra_only: bool) -> Option<Self> {
+        Profile::BY_DEMAND.into_iter().find(|p| {
+            (intra_only || !p.is_intra()) && ProfileConstraint::from(p).supports(chroma, bit_depth)
+        })
+    }
+
+    /// Whether this is one of the screen content coding (SCC) profiles
+    pub fn is_screen_content(&self) -> bool {
+        matches!(
+            self,
+            Profile::ScreenExtendedMain
+                | Profile::ScreenExtendedMain10
+                | Profile::ScreenExtendedMain444
+                | Profile::ScreenExtendedMain444_10
+        )
+    }
+
+    /// maxDpbPicBuf, SCC streams using the current picture as a reference
+    /// (sps_curr_pic_ref_enabled_flag) get an extra slot for it
+    pub fn max_dpb_pic_buf(&self, curr_pic_ref_enabled: bool) -> u32 {
+        if self.is_screen_content() && curr_pic_ref_enabled {
+            7
+        } else {
+            6
+        }
+    }
+
+    /// MinCrScaleFactor from Table A.3, 4:2:2 and 4:4:4 profiles may compress half as
+    /// much
+    pub fn min_cr_scale_factor(&self) -> f64 {
+        match self {
+            Profile::Main
+            | Profile::Main10
+            | Profile::Main12
+            | Profile::ScreenExtendedMain
+            | Profile::ScreenExtendedMain10 => 1.0,
+            Profile::Main422_10
+            | Profile::Main444
+            | Profile::Main444_16Intra
+            | Profile::Main444_16IntraHighThroughput
+            | Profile::ScreenExtendedMain444
+            | Profile::ScreenExtendedMain444_10 => 0.5,
+        }
+    }
+
+    /// FormatCapabilityFactor, bytes per luma sample of an uncompressed picture at the
+    /// profile's largest bit depth and chroma format
+    pub fn format_capability_factor(&self) -> f64 {
         let spec = ProfileConstraint::from(self);
-        let pixel_multiplier = match spec.max_chroma_format() {
-            ChromaSampling::Cs420 => match spec.max_bit_depth {
-                Depth::Depth8 => 1.0,
-                Depth::Depth10 => 1.0,
-                Depth::Depth12 => 1.5,
-                Depth::Depth16 => 3.0,
-            },
-            ChromaSampling::Cs422 => match spec.max_bit_depth {
-                Depth::Depth8 => 2.0,
-                Depth::Depth10 => 2.0,
-                Depth::Depth12 => 3.0,
-                Depth::Depth16 => 6.0,
-            },
-            ChromaSampling::Cs444 => match spec.max_bit_depth {
-                Depth::Depth8 => 3.0,
-                Depth::Depth10 => 3.0,
-                Depth::Depth12 => 3.0,
-                Depth::Depth16 => 8.0,
-            },
-            ChromaSampling::Monochrome => match spec.max_bit_depth {
-                Depth::Depth8 => 1.0,
-                Depth::Depth10 => 1.0,
-                Depth::Depth12 => 1.5,
-                Depth::Depth16 => 3.0,
-            },
+        let bit_depth = match spec.max_bit_depth {
+            Depth::Depth8 => 8.0,
+            Depth::Depth10 => 10.0,
+            Depth::Depth12 => 12.0,
+            Depth::Depth16 => 16.0,
         };
-
-        let throughput_multiplier = match self {
-            Profile::Main444_16IntraHighThroughput => 12.0,
-            _ => 1.0,
+        let samples = match spec.max_chroma_format() {
+            ChromaSampling::Monochrome => 1.0,
+            ChromaSampling::Cs420 => 1.5,
+            ChromaSampling::Cs422 => 2.0,
+            ChromaSampling::Cs444 => 3.0,
         };
+        bit_depth * samples / 8.0
+    }
 
-        pixel_multiplier * throughput_multiplier
+    /// CpbVclFactor from Table A.3 (A.2 before the SCC edition), bits per MaxBR and
+    /// MaxCPB unit for the VCL HRD
+    pub fn cpb_vcl_factor(&self) -> u64 {
+        match self {
+            Profile::Main
+            | Profile::Main10
+            | Profile::ScreenExtendedMain
+            | Profile::ScreenExtendedMain10 => 1000,
+            Profile::Main12 => 1500,
+            Profile::Main422_10 => 1667,
+            Profile::Main444 | Profile::ScreenExtendedMain444 => 2000,
+            Profile::ScreenExtendedMain444_10 => 2500,
+            Profile::Main444_16Intra | Profile::Main444_16IntraHighThroughput => 4000,
+        }
+    }
+
+    /// CpbNalFactor from Table A.3, bits per MaxBR and MaxCPB unit for the NAL HRD
+    pub fn cpb_nal_factor(&self) -> u64 {
+        match self {
+            Profile::Main
+            | Profile::Main10
+            | Profile::ScreenExtendedMain
+            | Profile::ScreenExtendedMain10 => 1100,
+            Profile::Main12 => 1650,
+            Profile::Main422_10 => 1833,
+            Profile::Main444 | Profile::ScreenExtendedMain444 => 2200,
+            Profile::ScreenExtendedMain444_10 => 2750,
+            Profile::Main444_16Intra | Profile::Main444_16IntraHighThroughput => 4400,
+        }
+    }
+
+    /// HbrFactor, the bitrate but not the CPB size of the high throughput profile is
+    /// scaled by it
+    fn hbr_factor(&self) -> u64 {
+        match self {
+            Profile::Main444_16IntraHighThroughput => 12,
+            _ => 1,
+        }
     }
 }
 
@@ -225,6 +601,32 @@ impl From<&Profile> for ProfileConstraint {
                     ChromaSampling::Cs444,
                 ],
             ),
+            Profile::ScreenExtendedMain => ProfileConstraint::new(
+                yuv::color::Depth::Depth8,
+                vec![ChromaSampling::Monochrome, ChromaSampling::Cs420],
+            ),
+            Profile::ScreenExtendedMain10 => ProfileConstraint::new(
+                yuv::color::Depth::Depth10,
+                vec![ChromaSampling::Monochrome, ChromaSampling::Cs420],
+            ),
+            Profile::ScreenExtendedMain444 => ProfileConstraint::new(
+                yuv::color::Depth::Depth8,
+                vec![
+                    ChromaSampling::Monochrome,
+                    ChromaSampling::Cs420,
+                    ChromaSampling::Cs422,
+                    ChromaSampling::Cs444,
+                ],
+            ),
+            Profile::ScreenExtendedMain444_10 => ProfileConstraint::new(
+                yuv::color::Depth::Depth10,
+                vec![
+                    ChromaSampling::Monochrome,
+                    ChromaSampling::Cs420,
+                    ChromaSampling::Cs422,
+                    ChromaSampling::Cs444,
+                ],
+            ),
         }
     }
 }
@@ -338,8 +740,24 @@ pub struct LevelSpecification {
     max_luma_sample_rate: u64,
     /// MaxLumaPs (samples) per picture
     max_luma_picture_size: u32,
+    /// MaxBR in kbit/s for the Main tier, before the profile multiplier
     max_bit_rate_main: u32,
+    /// MaxBR in kbit/s for the High tier, before the profile multiplier
     max_bit_rate_high: Option<u32>,
+    /// MaxCPB in kbit for the Main tier, before the profile multiplier
+    max_cpb_size_main: u32,
+    /// MaxCPB in kbit for the High tier, before the profile multiplier
+    max_cpb_size_high: Option<u32>,
+    /// MinCrBase for the Main tier
+    min_cr_base_main: u32,
+    /// MinCrBase for the High tier
+    min_cr_base_high: Option<u32>,
+    /// MaxSliceSegmentsPerPicture
+    max_slice_segments: u32,
+    /// MaxTileRows
+    max_tile_rows: u32,
+    /// MaxTileCols
+    max_tile_cols: u32,
 }
 
 impl LevelSpecification {
@@ -355,33 +773,255 @@ impl LevelSpecification {
         self.max_luma_picture_size
     }
 
-    pub fn max_bit_rate(&self, profile: Profile, tier: Tier) -> Option<u64> {
-        match tier {
-            Tier::Main => {
-                Some((self.max_bit_rate_main as f64 * profile.bitrate_multiplier()) as u64)
-            }
-            Tier::High => self
-                .max_bit_rate_high
-                .map(|v| (v as f64 * profile.bitrate_multiplier()) as u64),
+    /// Max bitrate for the VCL or NAL HRD, MaxBR times CpbVclFactor or CpbNalFactor and
+    /// HbrFactor
+    pub fn max_hrd_bit_rate(&self, profile: Profile, tier: Tier, hrd: HrdType) -> Option<Bitrate> {
+        let units = match tier {
+            Tier::Main => Some(self.max_bit_rate_main),
+            Tier::High => self.max_bit_rate_high,
+        }?;
+        let factor = match hrd {
+            HrdType::Vcl => profile.cpb_vcl_factor(),
+            HrdType::Nal => profile.cpb_nal_factor(),
+        };
+        Some(Bitrate::from_bps(
+            units as u64 * factor * profile.hbr_factor(),
+        ))
+    }
+
+    /// MaxCPB in bits for the VCL or NAL HRD, MaxCPB times CpbVclFactor or CpbNalFactor.
+    /// `None` when the tier isn't defined for the level
+    pub fn max_cpb_size(&self, profile: Profile, tier: Tier, hrd: HrdType) -> Option<u64> {
+        let units = match tier {
+            Tier::Main => Some(self.max_cpb_size_main),
+            Tier::High => self.max_cpb_size_high,
+        }?;
+        Some(match hrd {
+            HrdType::Vcl => units as u64 * profile.cpb_vcl_factor(),
+            HrdType::Nal => units as u64 * profile.cpb_nal_factor(),
+        })
+    }
+
+    /// The largest VBV settings an encoder can use and still stay within the level
+    pub fn vbv(&self, profile: Profile, tier: Tier, hrd: HrdType) -> Option<Vbv> {
+        Some(Vbv {
+            max_rate: self.max_hrd_bit_rate(profile, tier, hrd)?,
+            buffer_size: self.max_cpb_size(profile, tier, hrd)?,
+        })
+    }
+
+    /// HRD syntax values for an encoder running at `target`, which has to be within the
+    /// level's limits for the profile and tier and at least the smallest the syntax can
+    /// signal
+    pub fn hrd_parameters(
+        &self,
+        profile: Profile,
+        tier: Tier,
+        hrd: HrdType,
+        target: Vbv,
+        mode: RateMode,
+    ) -> Result<HrdParameters, SelectionError> {
+        let max = self
+            .vbv(profile, tier, hrd)
+            .ok_or(SelectionError::TierUnavailable)?;
+        if target.max_rate > max.max_rate {
+            return Err(SelectionError::Bitrate);
+        }
+        if target.buffer_size > max.buffer_size {
+            return Err(SelectionError::CpbSize);
+        }
+        HrdParameters::new(target, mode).map_err(|e| match e {
+            HrdError::InvalidCpbSize => SelectionError::CpbSize,
+            _ => SelectionError::Bitrate,
+        })
+    }
+
+    /// MinCr = Max(1, MinCrBase * MinCrScaleFactor), `None` when the tier isn't defined
+    /// for the level
+    pub fn min_compression_ratio(&self, profile: Profile, tier: Tier) -> Option<f64> {
+        let base = match tier {
+            Tier::Main => Some(self.min_cr_base_main),
+            Tier::High => self.min_cr_base_high,
+        }?;
+        Some((base as f64 * profile.min_cr_scale_factor()).max(1.0))
+    }
+
+    /// Largest coded picture in bytes at a steady framerate, FormatCapabilityFactor *
+    /// Max(PicSizeInSamplesY, MaxLumaSr / framerate) / MinCr from A.4.2
+    pub fn max_coded_picture_bytes(
+        &self,
+        width: u32,
+        height: u32,
+        profile: Profile,
+        tier: Tier,
+        framerate: impl Into<Framerate>,
+    ) -> Result<u64, SelectionError> {
+        let framerate = framerate.into();
+        common::validate(width, height, framerate)?;
+        let min_cr = self
+            .min_compression_ratio(profile, tier)
+            .ok_or(SelectionError::TierUnavailable)?;
+        let samples = width as u64 * height as u64;
+        let samples_per_frame = u64::try_from(
+            self.max_luma_sample_rate as u128 * framerate.den() as u128 / framerate.num() as u128,
+        )
+        .map_err(|_| SelectionError::Overflow)?;
+        let bytes = profile.format_capability_factor() * samples.max(samples_per_frame) as f64;
+        Ok((bytes / min_cr) as u64)
+    }
+
+    pub fn max_slice_segments(&self) -> u32 {
+        self.max_slice_segments
+    }
+
+    pub fn max_tile_rows(&self) -> u32 {
+        self.max_tile_rows
+    }
+
+    pub fn max_tile_cols(&self) -> u32 {
+        self.max_tile_cols
+    }
+
+    /// Checks a tile grid against MaxTileCols/MaxTileRows and the minimum tile size of
+    /// 256 x 64 luma samples. A single tile is always allowed.
+    pub fn validate_tiles(
+        &self,
+        width: u32,
+        height: u32,
+        layout: &TileLayout,
+    ) -> Result<(), TileError> {
+        if width == 0 || height == 0 {
+            return Err(TileError::InvalidDimensions);
+        }
+        if !CTB_SIZES.contains(&layout.block_size) {
+            return Err(TileError::InvalidBlockSize);
+        }
+        if !layout.covers(width, height) {
+            return Err(TileError::Mismatch);
+        }
+        if layout.columns() > self.max_tile_cols {
+            return Err(TileError::TooManyColumns);
+        }
+        if layout.rows() > self.max_tile_rows {
+            return Err(TileError::TooManyRows);
+        }
+        if layout.tiles() == 1 {
+            return Ok(());
         }
+        if let Some(i) = layout
+            .column_widths
+            .iter()
+            .position(|&w| w * layout.block_size < MIN_TILE_WIDTH)
+        {
+            return Err(TileError::TooNarrow(i));
+        }
+        if let Some(i) = layout
+            .row_heights
+            .iter()
+            .position(|&h| h * layout.block_size < MIN_TILE_HEIGHT)
+        {
+            return Err(TileError::TooShort(i));
+        }
+        Ok(())
+    }
+
+    /// The uniformly spaced grid with the most tiles the level allows for the picture
+    pub fn max_parallel_tiles(
+        &self,
+        width: u32,
+        height: u32,
+        ctb_size: u32,
+    ) -> Result<TileLayout, TileError> {
+        if !CTB_SIZES.contains(&ctb_size) {
+            return Err(TileError::InvalidBlockSize);
+        }
+        let most = |limit: u32, blocks: u32, min_size: u32| {
+            (1..=limit.min(blocks))
+                .rev()
+                .find(|&count| count == 1 || (blocks / count) * ctb_size >= min_size)
+                .unwrap_or(1)
+        };
+        let columns = most(self.max_tile_cols, width.div_ceil(ctb_size), MIN_TILE_WIDTH);
+        let rows = most(
+            self.max_tile_rows,
+            height.div_ceil(ctb_size),
+            MIN_TILE_HEIGHT,
+        );
+        let layout = TileLayout::uniform(width, height, ctb_size, columns, rows)?;
+        self.validate_tiles(width, height, &layout)?;
+        Ok(layout)
     }
 
-    pub fn max_decoder_picture_buffer_size(&self, width: u32, height: u32) -> u32 {
-        let luma_samples = width * height;
-        let max_dpb_pic_buf = 6;
+    /// Largest picture width in luma samples, sqrt(MaxLumaPs * 8)
+    pub fn max_width(&self) -> u32 {
+        (self.max_luma_picture_size as u64 * 8).isqrt() as u32
+    }
 
-        if luma_samples <= self.max_luma_picture_size >> 2 {
+    /// Largest picture height in luma samples, sqrt(MaxLumaPs * 8)
+    pub fn max_height(&self) -> u32 {
+        self.max_width()
+    }
+
+    /// Max bitrate for the profile and tier, the VCL HRD limit
+    pub fn max_bit_rate(&self, profile: Profile, tier: Tier) -> Option<Bitrate> {
+        self.max_hrd_bit_rate(profile, tier, HrdType::Vcl)
+    }
+
+    /// MaxDpbSize from Annex A for a picture of `width` x `height`, `curr_pic_ref_enabled`
+    /// is sps_curr_pic_ref_enabled_flag and only matters for the SCC profiles
+    pub fn max_decoder_picture_buffer_size(
+        &self,
+        width: u32,
+        height: u32,
+        profile: Profile,
+        curr_pic_ref_enabled: bool,
+    ) -> Result<u32, SelectionError> {
+        if width == 0 || height == 0 {
+            return Err(SelectionError::InvalidDimensions);
+        }
+        let luma_samples = width as u64 * height as u64;
+        let max_luma_ps = self.max_luma_picture_size as u64;
+        if luma_samples > max_luma_ps {
+            return Err(SelectionError::PictureSize);
+        }
+        let max_dpb_pic_buf = profile.max_dpb_pic_buf(curr_pic_ref_enabled);
+
+        Ok(if luma_samples <= max_luma_ps >> 2 {
             std::cmp::min(4 * max_dpb_pic_buf, 16)
-        } else if luma_samples <= self.max_luma_picture_size >> 1 {
+        } else if luma_samples <= max_luma_ps >> 1 {
             std::cmp::min(2 * max_dpb_pic_buf, 16)
-        } else if luma_samples <= (3 * self.max_luma_picture_size) >> 2 {
+        } else if luma_samples <= (3 * max_luma_ps) >> 2 {
             std::cmp::min((4 * max_dpb_pic_buf) / 3, 16)
         } else {
             max_dpb_pic_buf
-        }
+        })
+    }
+}
+
+impl common::LevelSpec for LevelSpecification {
+    type Level = Level;
+
+    fn id(&self) -> Level {
+        self.id
+    }
+    fn max_luma_picture_size(&self) -> u64 {
+        self.max_luma_picture_size as u64
+    }
+    fn max_luma_sample_rate(&self) -> u64 {
+        self.max_luma_sample_rate
     }
 }
 
+/// CtbSizeY values allowed by the Main profiles
+pub const CTB_SIZES: [u32; 3] = [16, 32, 64];
+/// Smallest tile column in luma samples when there's more than one tile
+pub const MIN_TILE_WIDTH: u32 = 256;
+/// Smallest tile row in luma samples when there's more than one tile
+pub const MIN_TILE_HEIGHT: u32 = 64;
+
+/// Level 8.5 is unconstrained so it's never selected automatically
+const SELECTABLE_LEVELS: &[LevelSpecification] = LEVEL_DETAILS.split_last().unwrap().1;
+
 pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
     LevelSpecification {
         id: Level::L1,
@@ -389,6 +1029,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 36_864,
         max_bit_rate_main: 128,
         max_bit_rate_high: None,
+        max_cpb_size_main: 350,
+        max_cpb_size_high: None,
+        min_cr_base_main: 2,
+        min_cr_base_high: None,
+        max_slice_segments: 16,
+        max_tile_rows: 1,
+        max_tile_cols: 1,
     },
     LevelSpecification {
         id: Level::L2,
@@ -396,6 +1043,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 122_880,
         max_bit_rate_main: 1_500,
         max_bit_rate_high: None,
+        max_cpb_size_main: 1_500,
+        max_cpb_size_high: None,
+        min_cr_base_main: 2,
+        min_cr_base_high: None,
+        max_slice_segments: 16,
+        max_tile_rows: 1,
+        max_tile_cols: 1,
     },
     LevelSpecification {
         id: Level::L2_1,
@@ -403,6 +1057,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 245_760,
         max_bit_rate_main: 3_000,
         max_bit_rate_high: None,
+        max_cpb_size_main: 3_000,
+        max_cpb_size_high: None,
+        min_cr_base_main: 2,
+        min_cr_base_high: None,
+        max_slice_segments: 20,
+        max_tile_rows: 1,
+        max_tile_cols: 1,
     },
     LevelSpecification {
         id: Level::L3,
@@ -410,6 +1071,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 552_960,
         max_bit_rate_main: 6_000,
         max_bit_rate_high: None,
+        max_cpb_size_main: 6_000,
+        max_cpb_size_high: None,
+        min_cr_base_main: 2,
+        min_cr_base_high: None,
+        max_slice_segments: 30,
+        max_tile_rows: 2,
+        max_tile_cols: 2,
     },
     LevelSpecification {
         id: Level::L3_1,
@@ -417,6 +1085,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 983_040,
         max_bit_rate_main: 10_000,
         max_bit_rate_high: None,
+        max_cpb_size_main: 10_000,
+        max_cpb_size_high: None,
+        min_cr_base_main: 2,
+        min_cr_base_high: None,
+        max_slice_segments: 40,
+        max_tile_rows: 3,
+        max_tile_cols: 3,
     },
     LevelSpecification {
         id: Level::L4,
@@ -424,6 +1099,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 2_228_224,
         max_bit_rate_main: 12_000,
         max_bit_rate_high: Some(30_000),
+        max_cpb_size_main: 12_000,
+        max_cpb_size_high: Some(30_000),
+        min_cr_base_main: 4,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 75,
+        max_tile_rows: 5,
+        max_tile_cols: 5,
     },
     LevelSpecification {
         id: Level::L4_1,
@@ -431,6 +1113,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 2_228_224,
         max_bit_rate_main: 20_000,
         max_bit_rate_high: Some(50_000),
+        max_cpb_size_main: 20_000,
+        max_cpb_size_high: Some(50_000),
+        min_cr_base_main: 4,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 75,
+        max_tile_rows: 5,
+        max_tile_cols: 5,
     },
     LevelSpecification {
         id: Level::L5,
@@ -438,6 +1127,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 8_912_896,
         max_bit_rate_main: 25_000,
         max_bit_rate_high: Some(100_000),
+        max_cpb_size_main: 25_000,
+        max_cpb_size_high: Some(100_000),
+        min_cr_base_main: 6,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 200,
+        max_tile_rows: 11,
+        max_tile_cols: 10,
     },
     LevelSpecification {
         id: Level::L5_1,
@@ -445,6 +1141,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 8_912_896,
         max_bit_rate_main: 40_000,
         max_bit_rate_high: Some(160_000),
+        max_cpb_size_main: 40_000,
+        max_cpb_size_high: Some(160_000),
+        min_cr_base_main: 8,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 200,
+        max_tile_rows: 11,
+        max_tile_cols: 10,
     },
     LevelSpecification {
         id: Level::L5_2,
@@ -452,6 +1155,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 8_912_896,
         max_bit_rate_main: 60_000,
         max_bit_rate_high: Some(240_000),
+        max_cpb_size_main: 60_000,
+        max_cpb_size_high: Some(240_000),
+        min_cr_base_main: 8,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 200,
+        max_tile_rows: 11,
+        max_tile_cols: 10,
     },
     LevelSpecification {
         id: Level::L6,
@@ -459,6 +1169,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 35_651_584,
         max_bit_rate_main: 60_000,
         max_bit_rate_high: Some(240_000),
+        max_cpb_size_main: 60_000,
+        max_cpb_size_high: Some(240_000),
+        min_cr_base_main: 8,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 600,
+        max_tile_rows: 22,
+        max_tile_cols: 20,
     },
     LevelSpecification {
         id: Level::L6_1,
@@ -466,6 +1183,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 35_651_584,
         max_bit_rate_main: 120_000,
         max_bit_rate_high: Some(480_000),
+        max_cpb_size_main: 120_000,
+        max_cpb_size_high: Some(480_000),
+        min_cr_base_main: 8,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 600,
+        max_tile_rows: 22,
+        max_tile_cols: 20,
     },
     LevelSpecification {
         id: Level::L6_2,
@@ -473,6 +1197,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 35_651_584,
         max_bit_rate_main: 240_000,
         max_bit_rate_high: Some(800_000),
+        max_cpb_size_main: 240_000,
+        max_cpb_size_high: Some(800_000),
+        min_cr_base_main: 6,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 600,
+        max_tile_rows: 22,
+        max_tile_cols: 20,
     },
     LevelSpecification {
         id: Level::L6_3,
@@ -480,6 +1211,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 35_651_584,
         max_bit_rate_main: 240_000,
         max_bit_rate_high: Some(1_600_000),
+        max_cpb_size_main: 240_000,
+        max_cpb_size_high: Some(1_600_000),
+        min_cr_base_main: 6,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 600,
+        max_tile_rows: 22,
+        max_tile_cols: 20,
     },
     LevelSpecification {
         id: Level::L7,
@@ -487,6 +1225,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 142_606_336,
         max_bit_rate_main: 240_000,
         max_bit_rate_high: Some(1_600_000),
+        max_cpb_size_main: 240_000,
+        max_cpb_size_high: Some(1_600_000),
+        min_cr_base_main: 6,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 600,
+        max_tile_rows: 22,
+        max_tile_cols: 20,
     },
     LevelSpecification {
         id: Level::L7_1,
@@ -494,6 +1239,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 142_606_336,
         max_bit_rate_main: 480_000,
         max_bit_rate_high: Some(3_200_000),
+        max_cpb_size_main: 480_000,
+        max_cpb_size_high: Some(3_200_000),
+        min_cr_base_main: 6,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 600,
+        max_tile_rows: 22,
+        max_tile_cols: 20,
     },
     LevelSpecification {
         id: Level::L7_2,
@@ -501,6 +1253,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: 142_606_336,
         max_bit_rate_main: 960_000,
         max_bit_rate_high: Some(6_400_000),
+        max_cpb_size_main: 960_000,
+        max_cpb_size_high: Some(6_400_000),
+        min_cr_base_main: 6,
+        min_cr_base_high: Some(4),
+        max_slice_segments: 600,
+        max_tile_rows: 22,
+        max_tile_cols: 20,
     },
     LevelSpecification {
         id: Level::L8_5,
@@ -508,6 +1267,13 @@ pub const LEVEL_DETAILS: [LevelSpecification; 18] = [
         max_luma_picture_size: u32::MAX,
         max_bit_rate_main: u32::MAX,
         max_bit_rate_high: Some(u32::MAX),
+        max_cpb_size_main: u32::MAX,
+        max_cpb_size_high: Some(u32::MAX),
+        min_cr_base_main: 1,
+        min_cr_base_high: Some(1),
+        max_slice_segments: u32::MAX,
+        max_tile_rows: u32::MAX,
+        max_tile_cols: u32::MAX,
     },
 ];
 
@@ -522,50 +1288,91 @@ mod tests {
 
     #[test]
     fn max_bitrate() {
+        use crate::common::Bitrate;
         use crate::hevc::{self, Level, Profile, Tier};
 
         // test level 5.2
         let l = hevc::get(Level::L5_2);
         assert_eq!(l.id(), Level::L5_2);
-        assert_eq!(l.max_bit_rate(Profile::Main, Tier::Main), Some(60_000));
-        assert_eq!(l.max_bit_rate(Profile::Main12, Tier::Main), Some(90_000));
-        assert_eq!(l.max_bit_rate(Profile::Main444, Tier::Main), Some(180_000));
+        assert_eq!(
+            l.max_bit_rate(Profile::Main, Tier::Main),
+            Some(Bitrate::from_kbps(60_000))
+        );
+        assert_eq!(
+            l.max_bit_rate(Profile::Main12, Tier::Main),
+            Some(Bitrate::from_kbps(90_000))
+        );
+        // MaxBR times CpbVclFactor, 2000 for Main 4:4:4 and 4000 for the 16 bit intra
+        // profiles, with HbrFactor 12 on top for high throughput
+        assert_eq!(
+            l.max_bit_rate(Profile::Main444, Tier::Main),
+            Some(Bitrate::from_kbps(120_000))
+        );
         assert_eq!(
             l.max_bit_rate(Profile::Main444_16Intra, Tier::Main),
-            Some(480_000)
+            Some(Bitrate::from_kbps(240_000))
         );
         assert_eq!(
             l.max_bit_rate(Profile::Main444_16IntraHighThroughput, Tier::Main),
-            Some(5_760_000)
+            Some(Bitrate::from_kbps(2_880_000))
+        );
+        assert_eq!(
+            l.max_bit_rate(Profile::Main444, Tier::High),
+            Some(Bitrate::from_kbps(480_000))
         );
-        assert_eq!(l.max_bit_rate(Profile::Main444, Tier::High), Some(720_000));
         assert_eq!(
             l.max_bit_rate(Profile::Main444_16Intra, Tier::High),
-            Some(1_920_000)
+            Some(Bitrate::from_kbps(960_000))
         );
         assert_eq!(
             l.max_bit_rate(Profile::Main444_16IntraHighThroughput, Tier::High),
-            Some(23_040_000)
+            Some(Bitrate::from_kbps(11_520_000))
         );
 
         // test level 2
         let l = hevc::get(Level::L2);
         assert_eq!(l.id(), Level::L2);
-        assert_eq!(l.max_bit_rate(Profile::Main, Tier::Main), Some(1_500));
+        assert_eq!(
+            l.max_bit_rate(Profile::Main, Tier::Main),
+            Some(Bitrate::from_kbps(1_500))
+        );
     }
 
     #[test]
     fn max_dpb_pic_buf() {
-        use crate::hevc::Level;
+        use crate::common::SelectionError;
+        use crate::hevc::{Level, LevelSpecification, Profile};
+
+        let dpb = |l: &LevelSpecification, w, h| {
+            l.max_decoder_picture_buffer_size(w, h, Profile::Main, false)
+        };
 
         let l = crate::hevc::get(Level::L4);
-        assert_eq!(l.max_decoder_picture_buffer_size(1280, 720), 12);
-        assert_eq!(l.max_decoder_picture_buffer_size(1920, 1080), 6);
+        assert_eq!(dpb(&l, 1280, 720), Ok(12));
+        assert_eq!(dpb(&l, 1920, 1080), Ok(6));
 
         let l = crate::hevc::get(Level::L5_2);
-        assert_eq!(l.max_decoder_picture_buffer_size(1920, 1080), 16);
-        assert_eq!(l.max_decoder_picture_buffer_size(2560, 1440), 12);
-        assert_eq!(l.max_decoder_picture_buffer_size(3840, 2160), 6);
+        assert_eq!(dpb(&l, 1920, 1080), Ok(16));
+        assert_eq!(dpb(&l, 2560, 1440), Ok(12));
+        assert_eq!(dpb(&l, 3840, 2160), Ok(6));
+
+        // Sizes that used to overflow u32 or make no sense, a picture above MaxLumaPs
+        // has no MaxDpbSize at all
+        assert_eq!(dpb(&l, 65536, 65536), Err(SelectionError::PictureSize));
+        assert_eq!(dpb(&l, 0, 1080), Err(SelectionError::InvalidDimensions));
+        let l = crate::hevc::get(Level::L8_5);
+        assert_eq!(dpb(&l, 3840, 2160), Ok(16));
+
+        // SCC with the current picture as reference gets maxDpbPicBuf = 7
+        let l = crate::hevc::get(Level::L4);
+        let scc = |w, h, curr_pic_ref| {
+            l.max_decoder_picture_buffer_size(w, h, Profile::ScreenExtendedMain, curr_pic_ref)
+        };
+        assert_eq!(scc(1920, 1080, false), Ok(6));
+        assert_eq!(scc(1920, 1080, true), Ok(7));
+        assert_eq!(scc(1600, 1024, true), Ok(9));
+        assert_eq!(scc(1280, 720, true), Ok(14));
+        assert_eq!(scc(960, 540, true), Ok(16));
     }
 
     #[test]
@@ -626,6 +1433,7 @@ mod tests {
 
     #[test]
     fn select_clamp_cases() {
+        use crate::common::SelectionError;
         use crate::hevc::{Level, LevelSelector, Profile, Tier};
 
         assert_eq!(
@@ -642,19 +1450,40 @@ mod tests {
             Level::L5_2
         );
 
-        assert!(LevelSelector::new()
-            .width(1920)
-            .height(1080)
-            .framerate(60.0)
-            .tier(Tier::Main)
-            .profile(Profile::Main)
-            .clamp(Level::L2, Level::L2_1)
-            .select()
-            .is_none());
+        assert_eq!(
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(60.0)
+                .tier(Tier::Main)
+                .profile(Profile::Main)
+                .clamp(Level::L2, Level::L2_1)
+                .select()
+                .unwrap_err(),
+            SelectionError::ClampRange
+        );
+
+        assert_eq!(
+            LevelSelector::new()
+                .tier(Tier::High)
+                .clamp(Level::L2, Level::L3_1)
+                .select()
+                .unwrap_err(),
+            SelectionError::TierUnavailable
+        );
+
+        assert_eq!(
+            LevelSelector::new()
+                .clamp(Level::L5, Level::L4)
+                .select()
+                .unwrap_err(),
+            SelectionError::ClampRange
+        );
     }
 
     #[test]
     fn select_bitrate_cases() {
+        use crate::common::Bitrate;
         use crate::hevc::{Level, LevelSelector, Profile, Tier};
         assert_eq!(
             LevelSelector::new()
@@ -663,11 +1492,648 @@ mod tests {
                 .framerate(60.0)
                 .tier(Tier::Main)
                 .profile(Profile::Main)
-                .max_bitrate(80000)
+                .max_bitrate(Bitrate::from_kbps(80_000))
                 .select()
                 .unwrap()
                 .id(),
             Level::L6_1
         );
     }
+
+    #[test]
+    fn select_explained() {
+        use crate::common::{Check, Limit};
+        use crate::hevc::{Level, LevelSelector};
+
+        let explanation = LevelSelector::new()
+            .width(3840)
+            .height(2160)
+            .framerate(60.0)
+            .select_explained();
+
+        assert_eq!(explanation.selected.unwrap().id(), Level::L5_1);
+        assert_eq!(explanation.rejected.len(), 8);
+
+        let l5 = explanation.rejected.last().unwrap();
+        assert_eq!(l5.level, Level::L5);
+        assert_eq!(
+            l5.failed().collect::<Vec<_>>(),
+            vec![&Check {
+                limit: Limit::LumaSampleRate,
+                requested: 497_664_000,
+                max: Some(267_386_880),
+            }]
+        );
+        assert_eq!(
+            l5.failed().next().unwrap().to_string(),
+            "max_luma_sample_rate: 497664000 > 267386880"
+        );
+    }
+
+    #[test]
+    fn utilization() {
+        use crate::common::Limit;
+        use crate::hevc::{self, Level, LevelSelector};
+
+        let selector = LevelSelector::new()
+            .width(3840)
+            .height(2160)
+            .framerate(60.0)
+            .dpb_frames(4);
+        let report = selector.utilization(&hevc::get(Level::L5_1)).unwrap();
+
+        let used = |limit| {
+            let check = report.iter().find(|c| c.limit == limit).unwrap();
+            (check.utilization().unwrap() * 100.0).round()
+        };
+        assert_eq!(used(Limit::LumaSampleRate), 93.0);
+        assert_eq!(used(Limit::PictureSize), 93.0);
+        assert_eq!(used(Limit::Bitrate), 0.0);
+        // 4 of the 6 frames a full size picture gets
+        assert_eq!(used(Limit::DpbFrames), 67.0);
+    }
+
+    #[test]
+    fn interlaced() {
+        use crate::common::{Framerate, Limit};
+        use crate::hevc::{self, Level, LevelSelector};
+
+        // 1080i59.94 is coded as 1920x540 fields at 59.94 pictures per second
+        let selector = LevelSelector::new()
+            .width(1920)
+            .height(1080)
+            .framerate(Framerate::ntsc(30))
+            .interlaced(true);
+        let report = selector.utilization(&hevc::get(Level::L4)).unwrap();
+        let requested = |limit| report.iter().find(|c| c.limit == limit).unwrap().requested;
+        assert_eq!(requested(Limit::Height), 540);
+        assert_eq!(requested(Limit::PictureSize), 1_036_800);
+        assert_eq!(requested(Limit::LumaSampleRate), 62_145_855);
+        assert_eq!(selector.select().unwrap().id(), Level::L4);
+
+        // Half height pictures fit a smaller MaxLumaPs
+        let selector = LevelSelector::new().width(1600).height(1200).framerate(12);
+        assert_eq!(selector.select().unwrap().id(), Level::L4);
+        let selector = LevelSelector::new()
+            .width(1600)
+            .height(1200)
+            .framerate(12)
+            .interlaced(true);
+        assert_eq!(selector.select().unwrap().id(), Level::L3_1);
+    }
+
+    #[test]
+    fn select_headroom_cases() {
+        use crate::common::{Headroom, SelectionError};
+        use crate::hevc::{Level, LevelSelector};
+
+        // 4K60 uses 93% of L5.1's sample rate and of the picture size every L5 level
+        // shares, so 80% headroom needs the larger L6 picture
+        assert_eq!(
+            LevelSelector::new()
+                .width(3840)
+                .height(2160)
+                .framerate(60.0)
+                .headroom(Headroom::Utilization(0.8))
+                .select()
+                .unwrap()
+                .id(),
+            Level::L6
+        );
+
+        assert_eq!(
+            LevelSelector::new()
+                .headroom(Headroom::Levels(1))
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4_1
+        );
+
+        // Stepping up never goes past the clamp
+        assert_eq!(
+            LevelSelector::new()
+                .headroom(Headroom::Levels(2))
+                .clamp(Level::L3, Level::L4_1)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4_1
+        );
+
+        // Fractions outside (0, 1] would pass or fail every level
+        for fraction in [f64::NAN, 0.0, -0.5, 1.5] {
+            let selector = || LevelSelector::new().headroom(Headroom::Utilization(fraction));
+            assert_eq!(
+                selector().select().unwrap_err(),
+                SelectionError::InvalidHeadroom
+            );
+            assert_eq!(
+                selector().auto_tier().select_with_tier().unwrap_err(),
+                SelectionError::InvalidHeadroom
+            );
+        }
+        assert_eq!(
+            LevelSelector::new()
+                .headroom(Headroom::Utilization(1.0))
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+    }
+
+    #[test]
+    fn select_invalid_input() {
+        use crate::common::SelectionError;
+        use crate::hevc::LevelSelector;
+
+        assert_eq!(
+            LevelSelector::new().width(0).select().unwrap_err(),
+            SelectionError::InvalidDimensions
+        );
+        assert_eq!(
+            LevelSelector::new()
+                .framerate(f32::NAN)
+                .select()
+                .unwrap_err(),
+            SelectionError::InvalidFramerate
+        );
+        assert_eq!(
+            LevelSelector::new()
+                .width(u32::MAX)
+                .height(u32::MAX)
+                .framerate(u32::MAX)
+                .select()
+                .unwrap_err(),
+            SelectionError::Overflow
+        );
+    }
+
+    #[test]
+    fn select_format_cases() {
+        use crate::common::SelectionError;
+        use crate::hevc::{Level, LevelSelector, Profile};
+        use yuv::color::{ChromaSampling, Depth};
+
+        assert_eq!(
+            LevelSelector::new()
+                .profile(Profile::Main)
+                .chroma(ChromaSampling::Cs422)
+                .bit_depth(Depth::Depth10)
+                .select()
+                .unwrap_err(),
+            SelectionError::UnsupportedFormat
+        );
+
+        assert_eq!(
+            LevelSelector::new()
+                .profile(Profile::Main)
+                .bit_depth(Depth::Depth10)
+                .select()
+                .unwrap_err(),
+            SelectionError::UnsupportedFormat
+        );
+
+        assert_eq!(
+            LevelSelector::new()
+                .profile(Profile::Main422_10)
+                .chroma(ChromaSampling::Cs422)
+                .bit_depth(Depth::Depth10)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+    }
+
+    #[test]
+    fn auto_profile_cases() {
+        use crate::common::SelectionError;
+        use crate::hevc::{LevelSelector, Profile};
+        use yuv::color::{ChromaSampling, Depth};
+
+        let profile = |chroma, bit_depth, intra_only| {
+            LevelSelector::new()
+                .chroma(chroma)
+                .bit_depth(bit_depth)
+                .auto_profile(intra_only)
+                .resolve_profile()
+        };
+
+        assert_eq!(
+            profile(ChromaSampling::Cs420, Depth::Depth8, false),
+            Ok(Profile::Main)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs420, Depth::Depth10, false),
+            Ok(Profile::Main10)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs422, Depth::Depth10, false),
+            Ok(Profile::Main422_10)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs444, Depth::Depth8, false),
+            Ok(Profile::Main444)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs444, Depth::Depth10, false),
+            Err(SelectionError::UnsupportedFormat)
+        );
+        assert_eq!(
+            profile(ChromaSampling::Cs444, Depth::Depth10, true),
+            Ok(Profile::Main444_16Intra)
+        );
+
+        // A fixed profile set afterwards wins over the automatic pick
+        assert_eq!(
+            LevelSelector::new()
+                .auto_profile(false)
+                .profile(Profile::Main12)
+                .resolve_profile(),
+            Ok(Profile::Main12)
+        );
+    }
+
+    #[test]
+    fn select_auto_tier_cases() {
+        use crate::common::Bitrate;
+        use crate::hevc::{Level, LevelSelector, Tier};
+
+        let contribution = || {
+            LevelSelector::new()
+                .width(1920)
+                .height(1080)
+                .framerate(60)
+                .max_bitrate(Bitrate::from_mbps(40))
+        };
+
+        assert_eq!(contribution().select().unwrap().id(), Level::L5_1);
+        let (level, tier) = contribution().auto_tier().select_with_tier().unwrap();
+        assert_eq!((level.id(), tier), (Level::L4_1, Tier::High));
+
+        // Main tier is kept while it fits, below L4 there's no High tier to fall back to
+        let (level, tier) = LevelSelector::new()
+            .width(1280)
+            .height(720)
+            .framerate(30)
+            .max_bitrate(Bitrate::from_mbps(8))
+            .auto_tier()
+            .select_with_tier()
+            .unwrap();
+        assert_eq!((level.id(), tier), (Level::L3_1, Tier::Main));
+    }
+
+    #[test]
+    fn select_picture_limit_cases() {
+        use crate::common::SelectionError;
+        use crate::hevc::{self, Level, LevelSelector};
+
+        assert_eq!(hevc::get(Level::L4).max_width(), 4222);
+        assert_eq!(hevc::get(Level::L5).max_height(), 8444);
+
+        // An 8192x256 strip at 1fps has a tiny sample rate but needs L5's width limit
+        assert_eq!(
+            LevelSelector::new()
+                .width(8192)
+                .height(256)
+                .framerate(1)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L5
+        );
+
+        // A still frame larger than any level's MaxLumaPs
+        assert_eq!(
+            LevelSelector::new()
+                .width(16384)
+                .height(16384)
+                .framerate(1)
+                .select()
+                .unwrap_err(),
+            SelectionError::PictureSize
+        );
+    }
+
+    #[test]
+    fn select_reference_frame_cases() {
+        use crate::common::SelectionError;
+        use crate::hevc::{Level, LevelSelector, Profile};
+
+        // 1080p takes most of L4's MaxLumaPs so the DPB holds 6 pictures, 5 references
+        // plus the current picture
+        let selector = || LevelSelector::new().width(1920).height(1080).framerate(30);
+        assert_eq!(
+            selector().reference_frames(5).select().unwrap().id(),
+            Level::L4
+        );
+        assert_eq!(
+            selector().reference_frames(6).select().unwrap().id(),
+            Level::L5
+        );
+        assert_eq!(
+            selector().dpb_frames(17).select().unwrap_err(),
+            SelectionError::DpbSize
+        );
+
+        // Screen content using the current picture as a reference gets one more slot
+        assert_eq!(
+            selector()
+                .profile(Profile::ScreenExtendedMain)
+                .curr_pic_ref_enabled(true)
+                .reference_frames(6)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+    }
+
+    #[test]
+    fn max_cpb_size() {
+        use crate::common::{Bitrate, HrdType};
+        use crate::hevc::{self, Level, Profile, Tier};
+
+        // L1 is the one level where MaxCPB isn't MaxBR
+        let l = hevc::get(Level::L1);
+        assert_eq!(
+            l.max_cpb_size(Profile::Main, Tier::Main, HrdType::Vcl),
+            Some(350_000)
+        );
+        assert_eq!(
+            l.max_cpb_size(Profile::Main, Tier::High, HrdType::Vcl),
+            None
+        );
+
+        let l = hevc::get(Level::L4_1);
+        assert_eq!(
+            l.max_cpb_size(Profile::Main, Tier::High, HrdType::Nal),
+            Some(55_000_000)
+        );
+        assert_eq!(
+            l.max_cpb_size(Profile::Main12, Tier::Main, HrdType::Vcl),
+            Some(30_000_000)
+        );
+        assert_eq!(
+            l.max_hrd_bit_rate(Profile::Main, Tier::Main, HrdType::Nal),
+            Some(Bitrate::from_kbps(22_000))
+        );
+
+        let vbv = l.vbv(Profile::Main10, Tier::Main, HrdType::Vcl).unwrap();
+        assert_eq!(vbv.max_rate, Bitrate::from_kbps(20_000));
+        assert_eq!(vbv.buffer_size_kbits(), 20_000);
+
+        // RExt profiles scale by CpbVclFactor and CpbNalFactor from Table A.3
+        assert_eq!(
+            l.max_cpb_size(Profile::Main422_10, Tier::Main, HrdType::Vcl),
+            Some(33_340_000)
+        );
+        assert_eq!(
+            l.max_cpb_size(Profile::Main422_10, Tier::Main, HrdType::Nal),
+            Some(36_660_000)
+        );
+        assert_eq!(
+            l.max_hrd_bit_rate(Profile::Main422_10, Tier::Main, HrdType::Nal),
+            Some(Bitrate::from_bps(36_660_000))
+        );
+        let vbv = l.vbv(Profile::Main444, Tier::High, HrdType::Nal).unwrap();
+        assert_eq!(vbv.max_rate, Bitrate::from_kbps(110_000));
+        assert_eq!(vbv.buffer_size, 110_000_000);
+        // HbrFactor raises the bitrate of the high throughput profile but not its CPB
+        assert_eq!(
+            l.max_hrd_bit_rate(
+                Profile::Main444_16IntraHighThroughput,
+                Tier::Main,
+                HrdType::Vcl
+            ),
+            Some(Bitrate::from_kbps(960_000))
+        );
+        assert_eq!(
+            l.max_cpb_size(
+                Profile::Main444_16IntraHighThroughput,
+                Tier::Main,
+                HrdType::Vcl
+            ),
+            Some(80_000_000)
+        );
+    }
+
+    #[test]
+    fn hrd_parameters() {
+        use crate::common::{Bitrate, HrdType, SelectionError, Vbv};
+        use crate::hevc::{self, Level, Profile, Tier};
+        use crate::hrd::RateMode;
+
+        let target = Vbv {
+            max_rate: Bitrate::from_mbps(15),
+            buffer_size: 15_000_000,
+        };
+        let l = hevc::get(Level::L4_1);
+        let params = l
+            .hrd_parameters(
+                Profile::Main,
+                Tier::Main,
+                HrdType::Nal,
+                target,
+                RateMode::Cbr,
+            )
+            .unwrap();
+        assert_eq!(params.bit_rate(), Bitrate::from_mbps(15));
+        assert!(params.cbr_flag);
+
+        let l = hevc::get(Level::L4);
+        let params =
+            |tier| l.hrd_parameters(Profile::Main, tier, HrdType::Vcl, target, RateMode::Vbr);
+        assert_eq!(params(Tier::Main), Err(SelectionError::Bitrate));
+        assert!(params(Tier::High).is_ok());
+        let l = hevc::get(Level::L3);
+        assert_eq!(
+            l.hrd_parameters(
+                Profile::Main,
+                Tier::High,
+                HrdType::Vcl,
+                target,
+                RateMode::Vbr
+            ),
+            Err(SelectionError::TierUnavailable)
+        );
+    }
+
+    #[test]
+    fn select_cpb_cases() {
+        use crate::common::{HrdType, SelectionError};
+        use crate::hevc::{Level, LevelSelector};
+
+        // L4 Main has a 12 Mbit CPB, 13.2 Mbit for the NAL HRD
+        let selector = || LevelSelector::new().width(1920).height(1080).framerate(30);
+        assert_eq!(
+            selector().cpb_size(12_000_000).select().unwrap().id(),
+            Level::L4
+        );
+        assert_eq!(
+            selector()
+                .cpb_size(13_000_000)
+                .hrd(HrdType::Nal)
+                .select()
+                .unwrap()
+                .id(),
+            Level::L4
+        );
+        assert_eq!(
+            selector().cpb_size(13_000_000).select().unwrap().id(),
+            Level::L4_1
+        );
+        assert_eq!(
+            selector().cpb_size(1_000_000_000).select().unwrap_err(),
+            SelectionError::CpbSize
+        );
+    }
+
+    #[test]
+    fn max_coded_picture_bytes() {
+        use crate::common::SelectionError;
+        use crate::hevc::{self, Level, LevelSelector, Profile, Tier};
+
+        // MaxLumaSr / 60 is MaxLumaPs at L4.1, 1.5 bytes per sample and MinCr 4
+        let l = hevc::get(Level::L4_1);
+        assert_eq!(
+            l.max_coded_picture_bytes(1920, 1080, Profile::Main, Tier::Main, 60),
+            Ok(835_584)
+        );
+        assert_eq!(
+            l.max_coded_picture_bytes(1920, 1080, Profile::Main10, Tier::Main, 60),
+            Ok(1_044_480)
+        );
+        assert_eq!(Profile::Main444_16Intra.format_capability_factor(), 6.0);
+        assert_eq!(Profile::Main12.min_cr_scale_factor(), 1.0);
+        assert_eq!(Profile::Main422_10.min_cr_scale_factor(), 0.5);
+        assert_eq!(Profile::Main444.min_cr_scale_factor(), 0.5);
+        // MinCr 4 halves to 2, 2.5 bytes per sample
+        assert_eq!(
+            l.max_coded_picture_bytes(1920, 1080, Profile::Main422_10, Tier::Main, 60),
+            Ok(2_785_280)
+        );
+        assert_eq!(
+            l.max_coded_picture_bytes(1920, 1080, Profile::Main444, Tier::Main, 60),
+            Ok(3_342_336)
+        );
+        assert_eq!(
+            l.min_compression_ratio(Profile::Main444_16Intra, Tier::High),
+            Some(2.0)
+        );
+        assert_eq!(
+            hevc::get(Level::L3).max_coded_picture_bytes(1280, 720, Profile::Main, Tier::High, 30),
+            Err(SelectionError::TierUnavailable)
+        );
+
+        // L5 Main tier needs MinCr 6, High tier only 4
+        let selector = || {
+            LevelSelector::new()
+                .width(3840)
+                .height(2160)
+                .framerate(30)
+                .max_picture_bytes(2_500_000)
+        };
+        assert_eq!(selector().select().unwrap().id(), Level::L5_1);
+        let (level, tier) = selector().auto_tier().select_with_tier().unwrap();
+        assert_eq!((level.id(), tier), (Level::L5, Tier::High));
+    }
+
+    #[test]
+    fn tiles() {
+        use crate::common::{TileError, TileLayout};
+        use crate::hevc::{self, Level};
+
+        let l = hevc::get(Level::L6_1);
+        assert_eq!(
+            (l.max_slice_segments(), l.max_tile_rows(), l.max_tile_cols()),
+            (600, 22, 20)
+        );
+
+        // 8K is 120x68 CTBs of 64, 20 columns of 6 CTBs and 22 rows of 3 or 4
+        let layout = l.max_parallel_tiles(7680, 4320, 64).unwrap();
+        assert_eq!((layout.columns(), layout.rows()), (20, 22));
+        assert_eq!(layout.row_heights.iter().sum::<u32>(), 68);
+
+        // Level 7 keeps the tile and slice segment limits of level 6
+        let l7 = hevc::get(Level::L7_2);
+        assert_eq!(
+            (
+                l7.max_slice_segments(),
+                l7.max_tile_rows(),
+                l7.max_tile_cols()
+            ),
+            (600, 22, 20)
+        );
+        let layout = l7.max_parallel_tiles(15360, 8640, 64).unwrap();
+        assert_eq!((layout.columns(), layout.rows()), (20, 22));
+        let wide = TileLayout::uniform(15360, 8640, 64, 21, 22).unwrap();
+        assert_eq!(
+            l7.validate_tiles(15360, 8640, &wide),
+            Err(TileError::TooManyColumns)
+        );
+
+        // 1280 wide in CTBs of 16 can only be split 5 ways and keep 256 samples a column
+        let layout = hevc::get(Level::L5)
+            .max_parallel_tiles(1280, 720, 16)
+            .unwrap();
+        assert_eq!((layout.columns(), layout.rows()), (5, 11));
+
+        let l = hevc::get(Level::L4_1);
+        let grid = |columns, rows| TileLayout::uniform(1920, 1080, 64, columns, rows).unwrap();
+        assert_eq!(l.validate_tiles(1920, 1080, &grid(5, 5)), Ok(()));
+        assert_eq!(
+            l.validate_tiles(1920, 1080, &grid(6, 1)),
+            Err(TileError::TooManyColumns)
+        );
+        assert_eq!(
+            hevc::get(Level::L3).validate_tiles(1920, 1080, &grid(1, 3)),
+            Err(TileError::TooManyRows)
+        );
+
+        let mut layout = grid(2, 1);
+        layout.column_widths = vec![2, 28];
+        assert_eq!(
+            l.validate_tiles(1920, 1080, &layout),
+            Err(TileError::TooNarrow(0))
+        );
+        layout.column_widths = vec![2, 2];
+        assert_eq!(
+            l.validate_tiles(1920, 1080, &layout),
+            Err(TileError::Mismatch)
+        );
+        assert_eq!(
+            l.max_parallel_tiles(1920, 1080, 128),
+            Err(TileError::InvalidBlockSize)
+        );
+    }
+
+    #[test]
+    fn select_error_cases() {
+        use crate::common::{Bitrate, SelectionError};
+        use crate::hevc::{LevelSelector, Profile, Tier};
+
+        // Nothing past L7.2 is selected, the unconstrained L8.5 has to be asked for
+        assert_eq!(
+            LevelSelector::new()
+                .width(7680)
+                .height(4320)
+                .framerate(600.0)
+                .select()
+                .unwrap_err(),
+            SelectionError::SampleRate
+        );
+
+        assert_eq!(
+            LevelSelector::new()
+                .tier(Tier::Main)
+                .profile(Profile::Main)
+                .max_bitrate(Bitrate::from_kbps(2_000_000))
+                .select()
+                .unwrap_err(),
+            SelectionError::Bitrate
+        );
+    }
 }
@@ -0,0 +1,612 @@
+/// Annex C hypothetical reference decoder for H.264 and HEVC, also used for the AV1
+/// Annex E smoothing buffer
+///
+/// A leaky bucket model of the coded picture buffer (CPB), used to check a trace of
+/// coded frame sizes against the bitrate and CPB size of a level.
+use std::fmt;
+
+use crate::common::{Bitrate, HrdType, Vbv};
+
+/// Timestamps are in ticks of the 90 kHz clock the HRD delays are expressed in
+pub const CLOCK_RATE: u64 = 90_000;
+
+/// Arrival times that differ by less than this (in seconds) count as on time, so exact
+/// fits don't fail on float rounding
+const EPSILON: f64 = 1e-9;
+
+/// How bits arrive in the CPB
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateMode {
+    /// Bits arrive at the full bitrate all the time, gaps have to be filled with filler data
+    Cbr,
+    /// Bits of a frame don't arrive before its removal time minus the initial delay
+    #[default]
+    Vbr,
+}
+
+/// A coded frame, frames are given in decode order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Coded size in bits
+    pub bits: u64,
+    /// Decode timestamp in `CLOCK_RATE` ticks, the frame leaves the CPB at this time
+    pub dts: u64,
+    /// Presentation timestamp in `CLOCK_RATE` ticks
+    pub pts: u64,
+}
+
+impl Frame {
+    pub fn new(bits: u64, dts: u64, pts: u64) -> Self {
+        Self { bits, dts, pts }
+    }
+}
+
+/// Reasons a trace can't be simulated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HrdError {
+    /// The trace is empty
+    NoFrames,
+    /// The bitrate is zero, or below the 64 bit/s hrd_parameters() can signal
+    InvalidBitrate,
+    /// The CPB size is below the 16 bits hrd_parameters() can signal
+    InvalidCpbSize,
+    /// The frame at this index decodes before the previous one or is presented before
+    /// it's decoded
+    Timestamps(usize),
+}
+
+impl fmt::Display for HrdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HrdError::NoFrames => write!(f, "trace has no frames"),
+            HrdError::InvalidBitrate => write!(f, "bitrate must be non zero"),
+            HrdError::InvalidCpbSize => write!(f, "CPB size is too small to signal"),
+            HrdError::Timestamps(index) => write!(f, "frame {} is out of order", index),
+        }
+    }
+}
+
+impl std::error::Error for HrdError {}
+
+/// Outcome of running a trace through the HRD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HrdReport {
+    /// Frames that hadn't fully arrived by their removal time
+    pub underflows: Vec<usize>,
+    /// Frames whose removal came after the CPB had already gone past its size
+    pub overflows: Vec<usize>,
+    /// Highest CPB fullness in bits, reached just before a removal
+    pub max_fullness: u64,
+}
+
+impl HrdReport {
+    pub fn passes(&self) -> bool {
+        self.underflows.is_empty() && self.overflows.is_empty()
+    }
+}
+
+/// A CPB with the bitrate it's filled at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hrd {
+    bitrate: Bitrate,
+    cpb_size: u64,
+    mode: RateMode,
+    initial_delay: Option<u64>,
+}
+
+impl From<Vbv> for Hrd {
+    fn from(vbv: Vbv) -> Self {
+        Hrd::new(vbv.max_rate, vbv.buffer_size)
+    }
+}
+
+impl Hrd {
+    /// A VBR HRD, `cpb_size` is in bits
+    pub fn new(bitrate: Bitrate, cpb_size: u64) -> Self {
+        Self {
+            bitrate,
+            cpb_size,
+            mode: RateMode::Vbr,
+            initial_delay: None,
+        }
+    }
+    pub fn mode(mut self, mode: RateMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    /// initial_cpb_removal_delay in `CLOCK_RATE` ticks, defaults to the time it takes
+    /// the bitrate to fill the CPB
+    pub fn initial_delay(mut self, ticks: u64) -> Self {
+        self.initial_delay = Some(ticks);
+        self
+    }
+
+    pub fn bitrate(&self) -> Bitrate {
+        self.bitrate
+    }
+
+    pub fn cpb_size(&self) -> u64 {
+        self.cpb_size
+    }
+
+    /// initial_cpb_removal_delay in `CLOCK_RATE` ticks, rounded down
+    pub fn delay(&self) -> u64 {
+        self.initial_delay.unwrap_or_else(|| {
+            match (self.cpb_size as u128 * CLOCK_RATE as u128)
+                .checked_div(self.bitrate.bps() as u128)
+            {
+                Some(ticks) => ticks.min(u64::MAX as u128) as u64,
+                None => 0,
+            }
+        })
+    }
+
+    /// Runs the trace through the CPB and reports every frame that arrived late or found
+    /// the CPB overflowing
+    pub fn simulate(&self, frames: &[Frame]) -> Result<HrdReport, HrdError> {
+        validate(frames)?;
+        if self.bitrate.bps() == 0 {
+            return Err(HrdError::InvalidBitrate);
+        }
+        let rate = self.bitrate.bps() as f64;
+        let delay = match self.initial_delay {
+            Some(ticks) => ticks as f64 / CLOCK_RATE as f64,
+            None => self.cpb_size as f64 / rate,
+        };
+        let first_dts = frames[0].dts;
+
+        // Removal and arrival times in seconds, C.1.2 and C.1.1
+        let removal: Vec<f64> = frames
+            .iter()
+            .map(|f| delay + (f.dts - first_dts) as f64 / CLOCK_RATE as f64)
+            .collect();
+        let mut arrival = Vec::with_capacity(frames.len());
+        let mut final_arrival = 0.0_f64;
+        for (frame, removal) in frames.iter().zip(&removal) {
+            let initial = match self.mode {
+                RateMode::Cbr => final_arrival,
+                RateMode::Vbr => final_arrival.max(removal - delay),
+            };
+            final_arrival = initial + frame.bits as f64 / rate;
+            arrival.push((initial, final_arrival));
+        }
+
+        let mut report = HrdReport {
+            underflows: Vec::new(),
+            overflows: Vec::new(),
+            max_fullness: 0,
+        };
+        // Frames before `arrived` are fully in the CPB, `arrived_bits` is their size
+        let mut arrived = 0;
+        let mut arrived_bits = 0;
+        let mut removed_bits = 0;
+        for (index, frame) in frames.iter().enumerate() {
+            let time = removal[index];
+            if arrival[index].1 > time + EPSILON {
+                report.underflows.push(index);
+            }
+
+            while arrived < frames.len() && arrival[arrived].1 <= time {
+                arrived_bits += frames[arrived].bits;
+                arrived += 1;
+            }
+            let partial = match arrival.get(arrived) {
+                Some(&(initial, _)) if time > initial => {
+                    (((time - initial) * rate) as u64).min(frames[arrived].bits)
+                }
+                _ => 0,
+            };
+            let fullness = (arrived_bits + partial).saturating_sub(removed_bits);
+            if fullness > self.cpb_size {
+                report.overflows.push(index);
+            }
+            report.max_fullness = report.max_fullness.max(fullness);
+            removed_bits += frame.bits;
+        }
+
+        Ok(report)
+    }
+
+    /// Smallest CPB size in bits the trace passes with at this bitrate, `None` if even a
+    /// CPB holding the whole trace isn't enough
+    pub fn min_cpb_size(&self, frames: &[Frame]) -> Result<Option<u64>, HrdError> {
+        validate(frames)?;
+        let total = frames.iter().map(|f| f.bits).sum::<u64>();
+        search(total, |cpb_size| {
+            let hrd = Hrd { cpb_size, ..*self };
+            Ok(hrd.simulate(frames)?.passes())
+        })
+    }
+
+    /// Lowest bitrate at which no frame of the trace arrives late with this CPB size,
+    /// overflows are ignored since CBR streams fix those with filler data
+    pub fn min_bitrate(&self, frames: &[Frame]) -> Result<Option<Bitrate>, HrdError> {
+        validate(frames)?;
+        let total = frames.iter().map(|f| f.bits).sum::<u64>();
+        let max = total.saturating_mul(CLOCK_RATE);
+        let bps = search(max, |bps| {
+            let hrd = Hrd {
+                bitrate: Bitrate::from_bps(bps),
+                ..*self
+            };
+            Ok(hrd.simulate(frames)?.underflows.is_empty())
+        })?;
+        Ok(bps.map(Bitrate::from_bps))
+    }
+}
+
+/// Writes the MSB first bit strings the HRD syntax structures are made of
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// u(n), the low `bits` bits of `value`
+    pub fn write_bits(&mut self, value: u64, bits: u32) {
+        for bit in (0..bits).rev() {
+            self.write_flag((value >> bit) & 1 == 1);
+        }
+    }
+
+    /// u(1)
+    pub fn write_flag(&mut self, flag: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if flag {
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    /// ue(v), unsigned Exp-Golomb
+    pub fn write_ue(&mut self, value: u32) {
+        let code = value as u64 + 1;
+        let bits = u64::BITS - code.leading_zeros();
+        self.write_bits(0, bits - 1);
+        self.write_bits(code, bits);
+    }
+
+    /// Number of bits written
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The written bits, the last byte is padded with zeros
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Syntax element values of hrd_parameters() with a single CPB, plus the buffering
+/// period values that go with them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HrdParameters {
+    /// BitRate = (bit_rate_value_minus1 + 1) * 2^(6 + bit_rate_scale)
+    pub bit_rate_scale: u8,
+    pub bit_rate_value_minus1: u32,
+    /// CpbSize = (cpb_size_value_minus1 + 1) * 2^(4 + cpb_size_scale)
+    pub cpb_size_scale: u8,
+    pub cpb_size_value_minus1: u32,
+    pub cbr_flag: bool,
+    pub initial_cpb_removal_delay_length_minus1: u8,
+    pub cpb_removal_delay_length_minus1: u8,
+    pub dpb_output_delay_length_minus1: u8,
+    /// H.264 only
+    pub time_offset_length: u8,
+    /// Buffering period delay in `CLOCK_RATE` ticks, the time the CPB takes to fill
+    pub initial_cpb_removal_delay: u32,
+    pub initial_cpb_removal_delay_offset: u32,
+}
+
+impl HrdParameters {
+    /// Values for an encoder running at `target`, bitrate and CPB size are rounded down
+    /// to what the syntax can express so they never exceed the target. Targets below
+    /// one unit of the syntax, 64 bit/s or a 16 bit CPB, can't be signalled.
+    pub fn new(target: Vbv, mode: RateMode) -> Result<Self, HrdError> {
+        if target.max_rate.bps() < 1 << 6 {
+            return Err(HrdError::InvalidBitrate);
+        }
+        if target.buffer_size < 1 << 4 {
+            return Err(HrdError::InvalidCpbSize);
+        }
+        let (bit_rate_scale, bit_rate_value_minus1) = scaled(target.max_rate.bps(), 6);
+        let (cpb_size_scale, cpb_size_value_minus1) = scaled(target.buffer_size, 4);
+        let mut params = Self {
+            bit_rate_scale,
+            bit_rate_value_minus1,
+            cpb_size_scale,
+            cpb_size_value_minus1,
+            cbr_flag: mode == RateMode::Cbr,
+            initial_cpb_removal_delay_length_minus1: 23,
+            cpb_removal_delay_length_minus1: 23,
+            dpb_output_delay_length_minus1: 23,
+            time_offset_length: 24,
+            initial_cpb_removal_delay: 0,
+            initial_cpb_removal_delay_offset: 0,
+        };
+        let ticks =
+            params.cpb_size() as u128 * CLOCK_RATE as u128 / params.bit_rate().bps() as u128;
+        let max = (1u128 << (params.initial_cpb_removal_delay_length_minus1 + 1)) - 1;
+        params.initial_cpb_removal_delay = ticks.clamp(1, max) as u32;
+        Ok(params)
+    }
+
+    /// The bitrate the syntax elements signal
+    pub fn bit_rate(&self) -> Bitrate {
+        Bitrate::from_bps((self.bit_rate_value_minus1 as u64 + 1) << (6 + self.bit_rate_scale))
+    }
+
+    /// The CPB size in bits the syntax elements signal
+    pub fn cpb_size(&self) -> u64 {
+        (self.cpb_size_value_minus1 as u64 + 1) << (4 + self.cpb_size_scale)
+    }
+
+    /// H.264 hrd_parameters(), E.1.2
+    pub fn write_h264(&self, w: &mut BitWriter) {
+        w.write_ue(0); // cpb_cnt_minus1
+        w.write_bits(self.bit_rate_scale.into(), 4);
+        w.write_bits(self.cpb_size_scale.into(), 4);
+        w.write_ue(self.bit_rate_value_minus1);
+        w.write_ue(self.cpb_size_value_minus1);
+        w.write_flag(self.cbr_flag);
+        w.write_bits(self.initial_cpb_removal_delay_length_minus1.into(), 5);
+        w.write_bits(self.cpb_removal_delay_length_minus1.into(), 5);
+        w.write_bits(self.dpb_output_delay_length_minus1.into(), 5);
+        w.write_bits(self.time_offset_length.into(), 5);
+    }
+
+    /// HEVC hrd_parameters(1, 0) with only the `hrd` type present, E.2.2
+    pub fn write_hevc(&self, hrd: HrdType, w: &mut BitWriter) {
+        w.write_flag(hrd == HrdType::Nal); // nal_hrd_parameters_present_flag
+        w.write_flag(hrd == HrdType::Vcl); // vcl_hrd_parameters_present_flag
+        w.write_flag(false); // sub_pic_hrd_params_present_flag
+        w.write_bits(self.bit_rate_scale.into(), 4);
+        w.write_bits(self.cpb_size_scale.into(), 4);
+        w.write_bits(self.initial_cpb_removal_delay_length_minus1.into(), 5);
+        w.write_bits(self.cpb_removal_delay_length_minus1.into(), 5); // au_
+        w.write_bits(self.dpb_output_delay_length_minus1.into(), 5);
+        w.write_flag(false); // fixed_pic_rate_general_flag
+        w.write_flag(false); // fixed_pic_rate_within_cvs_flag
+        w.write_flag(false); // low_delay_hrd_flag
+        w.write_ue(0); // cpb_cnt_minus1
+
+        // sub_layer_hrd_parameters(0)
+        w.write_ue(self.bit_rate_value_minus1);
+        w.write_ue(self.cpb_size_value_minus1);
+        w.write_flag(self.cbr_flag);
+    }
+}
+
+/// Picks the scale for `value` = units * 2^(shift + scale) that keeps the most precision,
+/// returns the scale and units - 1
+fn scaled(value: u64, shift: u32) -> (u8, u32) {
+    let mut scale = value.trailing_zeros().saturating_sub(shift).min(15);
+    while scale < 15 && value >> (shift + scale) > u32::MAX as u64 {
+        scale += 1;
+    }
+    let units = (value >> (shift + scale)).clamp(1, u32::MAX as u64) as u32;
+    (scale as u8, units - 1)
+}
+
+fn validate(frames: &[Frame]) -> Result<(), HrdError> {
+    if frames.is_empty() {
+        return Err(HrdError::NoFrames);
+    }
+    for (index, frame) in frames.iter().enumerate() {
+        let decode_order = index == 0 || frame.dts >= frames[index - 1].dts;
+        if !decode_order || frame.pts < frame.dts {
+            return Err(HrdError::Timestamps(index));
+        }
+    }
+    Ok(())
+}
+
+/// Binary search for the lowest value in 1..=max that passes, assuming everything above
+/// a passing value passes as well
+fn search<F>(max: u64, passes: F) -> Result<Option<u64>, HrdError>
+where
+    F: Fn(u64) -> Result<bool, HrdError>,
+{
+    if max == 0 || !passes(max)? {
+        return Ok(None);
+    }
+    let (mut low, mut high) = (0, max);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if passes(mid)? {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    Ok(Some(high))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` frames of `bits` at 30 fps without reordering
+    fn constant(count: u64, bits: u64) -> Vec<Frame> {
+        (0..count)
+            .map(|n| Frame::new(bits, n * 3000, n * 3000))
+            .collect()
+    }
+
+    #[test]
+    fn simulate_cbr() {
+        // 3 Mbit/s at 30 fps is 100 kbit per frame, the CPB stays exactly full
+        let hrd = Hrd::new(Bitrate::from_mbps(3), 1_000_000).mode(RateMode::Cbr);
+        let report = hrd.simulate(&constant(300, 100_000)).unwrap();
+        assert!(report.passes());
+        assert_eq!(report.max_fullness, 1_000_000);
+
+        // Smaller frames leave the bits the CBR stream keeps sending nowhere to go
+        let report = hrd.simulate(&constant(300, 50_000)).unwrap();
+        assert!(report.underflows.is_empty());
+        assert_eq!(report.overflows.first(), Some(&1));
+
+        // VBR stops sending instead
+        let report = hrd.mode(RateMode::Vbr).simulate(&constant(300, 50_000));
+        assert!(report.unwrap().passes());
+    }
+
+    #[test]
+    fn simulate_underflow() {
+        let mut frames = constant(60, 80_000);
+        frames[30].bits = 1_500_000;
+        let hrd = Hrd::new(Bitrate::from_mbps(3), 1_000_000);
+        let report = hrd.simulate(&frames).unwrap();
+        assert_eq!(report.underflows.first(), Some(&30));
+        assert!(report.overflows.is_empty());
+    }
+
+    #[test]
+    fn minimums() {
+        let frames = constant(300, 100_000);
+        let hrd = Hrd::new(Bitrate::from_mbps(3), 1_000_000);
+        assert_eq!(hrd.min_cpb_size(&frames), Ok(Some(100_000)));
+
+        let hrd = Hrd::new(Bitrate::from_mbps(1), 100_000);
+        assert_eq!(
+            hrd.min_bitrate(&constant(3, 100_000)),
+            Ok(Some(Bitrate::from_mbps(3)))
+        );
+
+        // A fixed initial delay too short for the first frame can't be fixed by the CPB
+        let hrd = Hrd::new(Bitrate::from_mbps(3), 1_000_000).initial_delay(1);
+        assert_eq!(hrd.min_cpb_size(&frames), Ok(None));
+    }
+
+    #[test]
+    fn from_level() {
+        use crate::common::HrdType;
+        use crate::h264::{self, Level, Profile};
+
+        let hrd = Hrd::from(h264::get(Level::L4).vbv(Profile::High, HrdType::Vcl));
+        assert_eq!(hrd.bitrate(), Bitrate::from_kbps(25_000));
+        assert_eq!(hrd.delay(), 112_500);
+        // 24 Mbit/s passes, at 30 Mbit/s the 1.25 s of buffer runs dry before 10 s
+        assert!(hrd.simulate(&constant(300, 800_000)).unwrap().passes());
+        assert!(!hrd.simulate(&constant(300, 1_000_000)).unwrap().passes());
+    }
+
+    #[test]
+    fn exp_golomb() {
+        let mut w = BitWriter::new();
+        for v in [0, 1, 2, 3] {
+            w.write_ue(v);
+        }
+        // 1 010 011 00100
+        assert_eq!(w.len(), 12);
+        assert_eq!(w.into_bytes(), vec![0b1010_0110, 0b0100_0000]);
+    }
+
+    #[test]
+    fn hrd_parameters() {
+        let params = HrdParameters::new(
+            Vbv {
+                max_rate: Bitrate::from_mbps(8),
+                buffer_size: 8_000_000,
+            },
+            RateMode::Vbr,
+        )
+        .unwrap();
+        // 8000000 = 15625 * 2^9
+        assert_eq!(params.bit_rate_scale, 3);
+        assert_eq!(params.bit_rate_value_minus1, 15_624);
+        assert_eq!(params.cpb_size_scale, 5);
+        assert_eq!(params.cpb_size_value_minus1, 15_624);
+        assert_eq!(params.bit_rate(), Bitrate::from_mbps(8));
+        assert_eq!(params.initial_cpb_removal_delay, 90_000);
+
+        // Values the scale can't express exactly are rounded down
+        let params = HrdParameters::new(
+            Vbv {
+                max_rate: Bitrate::from_bps(1_000_001),
+                buffer_size: 17,
+            },
+            RateMode::Cbr,
+        )
+        .unwrap();
+        assert_eq!(params.bit_rate(), Bitrate::from_bps(1_000_000));
+        assert_eq!(params.cpb_size(), 16);
+
+        // Below one unit the value would have to be rounded up past the target
+        let target = |bps, buffer_size| Vbv {
+            max_rate: Bitrate::from_bps(bps),
+            buffer_size,
+        };
+        assert_eq!(
+            HrdParameters::new(target(63, 1000), RateMode::Vbr),
+            Err(HrdError::InvalidBitrate)
+        );
+        assert_eq!(
+            HrdParameters::new(target(64, 15), RateMode::Vbr),
+            Err(HrdError::InvalidCpbSize)
+        );
+        let params = HrdParameters::new(target(64, 16), RateMode::Vbr).unwrap();
+        assert_eq!((params.bit_rate().bps(), params.cpb_size()), (64, 16));
+    }
+
+    #[test]
+    fn hrd_parameters_syntax() {
+        use crate::h264::{self, Level, Profile};
+
+        let level = h264::get(Level::L4);
+        let params = level
+            .hrd_parameters(
+                Profile::High,
+                HrdType::Vcl,
+                level.vbv(Profile::High, HrdType::Vcl),
+                RateMode::Vbr,
+            )
+            .unwrap();
+        assert_eq!(params.bit_rate_value_minus1, 390_624);
+        assert_eq!(params.cpb_size_value_minus1, 1_953_124);
+        assert_eq!(params.initial_cpb_removal_delay, 112_500);
+
+        let mut w = BitWriter::new();
+        params.write_h264(&mut w);
+        // ue(0), two scales, ue(390624) and ue(1953124), cbr_flag and four lengths
+        assert_eq!(w.len(), 1 + 8 + 37 + 41 + 1 + 20);
+        assert_eq!(w.into_bytes()[0], 0x80);
+
+        let mut w = BitWriter::new();
+        params.write_hevc(HrdType::Vcl, &mut w);
+        assert_eq!(w.len(), 3 + 8 + 15 + 3 + 1 + 37 + 41 + 1);
+        assert_eq!(w.into_bytes()[0] >> 5, 0b010);
+    }
+
+    #[test]
+    fn invalid_traces() {
+        let hrd = Hrd::new(Bitrate::from_mbps(3), 1_000_000);
+        assert_eq!(hrd.simulate(&[]), Err(HrdError::NoFrames));
+        assert_eq!(
+            hrd.simulate(&[Frame::new(1, 3000, 3000), Frame::new(1, 0, 6000)]),
+            Err(HrdError::Timestamps(1))
+        );
+        assert_eq!(
+            hrd.simulate(&[Frame::new(1, 3000, 0)]),
+            Err(HrdError::Timestamps(0))
+        );
+        assert_eq!(
+            Hrd::new(Bitrate::from_bps(0), 1)
+                .simulate(&constant(1, 1))
+                .unwrap_err(),
+            HrdError::InvalidBitrate
+        );
+    }
+}
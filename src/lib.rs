@@ -6,3 +6,5 @@ pub mod common;
 pub mod h264;
 #[allow(dead_code)]
 pub mod hevc;
+#[allow(dead_code)]
+pub mod hrd;